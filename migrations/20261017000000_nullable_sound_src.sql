@@ -0,0 +1,2 @@
+-- audio may now live outside of the database, depending on the configured sound store
+ALTER TABLE sounds MODIFY COLUMN src MEDIUMBLOB;
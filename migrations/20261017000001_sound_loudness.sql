@@ -0,0 +1,4 @@
+-- integrated loudness (LUFS) and true peak (dBTP) measured before normalisation. NULL for silent audio
+ALTER TABLE sounds ADD COLUMN loudness DOUBLE;
+ALTER TABLE sounds ADD COLUMN peak DOUBLE;
+ALTER TABLE sounds ADD COLUMN normalised BOOL NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+ALTER TABLE sounds ADD COLUMN duration DOUBLE;
+ALTER TABLE sounds ADD COLUMN channels TINYINT UNSIGNED;
+ALTER TABLE sounds ADD COLUMN sample_rate INT UNSIGNED;
+ALTER TABLE sounds ADD COLUMN bitrate INT UNSIGNED;
+-- set once a sound has been probed, even if probing failed, so it is only backfilled once
+ALTER TABLE sounds ADD COLUMN metadata_checked BOOL NOT NULL DEFAULT 0;
+
+ALTER TABLE servers ADD COLUMN max_play_duration INT UNSIGNED;
+ALTER TABLE servers ADD COLUMN max_greet_duration INT UNSIGNED;
@@ -0,0 +1,32 @@
+-- audio is stored once per unique content hash, and shared between sounds
+CREATE TABLE sound_sources (
+    hash CHAR(64) NOT NULL,
+    ref_count INT UNSIGNED NOT NULL DEFAULT 0,
+    src MEDIUMBLOB,
+
+    PRIMARY KEY (hash)
+);
+
+ALTER TABLE sounds ADD COLUMN source_hash CHAR(64);
+
+UPDATE sounds SET source_hash = SHA2(src, 256) WHERE src IS NOT NULL;
+
+INSERT INTO sound_sources (hash, ref_count, src)
+    SELECT sounds.source_hash, counts.ref_count, sounds.src
+    FROM sounds
+    INNER JOIN (
+        SELECT MIN(id) AS id, COUNT(1) AS ref_count
+        FROM sounds
+        WHERE source_hash IS NOT NULL
+        GROUP BY source_hash
+    ) counts ON sounds.id = counts.id;
+
+-- audio held in the filesystem or S3 stores is keyed by sound ID rather than by hash. It is
+-- moved to its hash the next time the bot starts
+ALTER TABLE sounds ADD COLUMN legacy_source BOOL NOT NULL DEFAULT 0;
+
+UPDATE sounds SET legacy_source = 1 WHERE src IS NULL;
+
+ALTER TABLE sounds DROP COLUMN src;
+
+ALTER TABLE sounds ADD FOREIGN KEY (source_hash) REFERENCES sound_sources(hash);
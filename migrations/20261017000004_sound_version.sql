@@ -0,0 +1 @@
+ALTER TABLE sounds ADD COLUMN version INT UNSIGNED NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+-- 0 overlap, 1 interrupt, 2 queue, 3 overlap up to overlap_limit tracks
+ALTER TABLE servers ADD COLUMN playback_mode INT NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN overlap_limit TINYINT UNSIGNED NOT NULL DEFAULT 3;
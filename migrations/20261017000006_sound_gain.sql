@@ -0,0 +1,2 @@
+-- multiplier on the guild volume, set by the sound's uploader
+ALTER TABLE sounds ADD COLUMN gain FLOAT NOT NULL DEFAULT 1;
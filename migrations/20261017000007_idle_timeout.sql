@@ -0,0 +1,2 @@
+-- seconds without playback before leaving voice. NULL stays connected
+ALTER TABLE servers ADD COLUMN idle_timeout INT UNSIGNED;
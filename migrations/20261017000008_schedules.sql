@@ -0,0 +1,24 @@
+-- times are UTC. recurrence is a cron expression, NULL for schedules that run once
+CREATE TABLE schedules (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    channel_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    next_run DATETIME NOT NULL,
+    recurrence VARCHAR(100),
+
+    PRIMARY KEY (id),
+    INDEX (guild_id),
+    INDEX (next_run)
+);
+
+-- sounds played by a schedule, queued in order of position
+CREATE TABLE schedule_sounds (
+    schedule_id INT UNSIGNED NOT NULL,
+    position INT UNSIGNED NOT NULL,
+    sound_id INT UNSIGNED NOT NULL,
+
+    PRIMARY KEY (schedule_id, position),
+    FOREIGN KEY (schedule_id) REFERENCES schedules(id) ON DELETE CASCADE,
+    FOREIGN KEY (sound_id) REFERENCES sounds(id) ON DELETE CASCADE
+);
@@ -0,0 +1,23 @@
+-- owned by the user in user_id, or by the guild in guild_id when user_id is NULL
+CREATE TABLE playlists (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    name VARCHAR(32) NOT NULL,
+    guild_id BIGINT UNSIGNED,
+    user_id BIGINT UNSIGNED,
+
+    PRIMARY KEY (id),
+    -- the owner column that is NULL never conflicts, so each key only covers one kind of owner.
+    -- Names compare case-insensitively under the default collation
+    UNIQUE KEY (guild_id, name),
+    UNIQUE KEY (user_id, name)
+);
+
+CREATE TABLE playlist_sounds (
+    playlist_id INT UNSIGNED NOT NULL,
+    position INT UNSIGNED NOT NULL,
+    sound_id INT UNSIGNED NOT NULL,
+
+    PRIMARY KEY (playlist_id, position),
+    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+    FOREIGN KEY (sound_id) REFERENCES sounds(id) ON DELETE CASCADE
+);
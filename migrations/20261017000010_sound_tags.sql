@@ -0,0 +1,18 @@
+CREATE TABLE sound_tags (
+    sound_id INT UNSIGNED NOT NULL,
+    tag VARCHAR(20) NOT NULL,
+
+    PRIMARY KEY (sound_id, tag),
+    INDEX (tag),
+    FOREIGN KEY (sound_id) REFERENCES sounds(id) ON DELETE CASCADE
+);
+
+-- alternative names a sound can be played by
+CREATE TABLE sound_aliases (
+    sound_id INT UNSIGNED NOT NULL,
+    alias VARCHAR(20) NOT NULL,
+
+    PRIMARY KEY (sound_id, alias),
+    INDEX (alias),
+    FOREIGN KEY (sound_id) REFERENCES sounds(id) ON DELETE CASCADE
+);
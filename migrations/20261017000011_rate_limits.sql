@@ -0,0 +1,12 @@
+-- plays allowed per user in each window. NULL disables rate limiting
+ALTER TABLE servers ADD COLUMN rate_limit_plays TINYINT UNSIGNED;
+ALTER TABLE servers ADD COLUMN rate_limit_window INT UNSIGNED NOT NULL DEFAULT 10;
+
+-- roles whose members are never rate limited
+CREATE TABLE rate_limit_exempt_roles (
+    guild_id BIGINT UNSIGNED NOT NULL,
+    role_id BIGINT UNSIGNED NOT NULL,
+
+    PRIMARY KEY (guild_id, role_id),
+    FOREIGN KEY (guild_id) REFERENCES servers(id) ON DELETE CASCADE
+);
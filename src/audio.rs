@@ -0,0 +1,785 @@
+use std::{process::Output, str::FromStr};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+use crate::{consts::LOUDNESS_TARGET, error::ErrorTypes};
+
+/// Loudness statistics as reported by ffmpeg's `loudnorm` filter
+pub struct Loudness {
+    pub integrated: f64,
+    pub true_peak: f64,
+    pub range: f64,
+    pub threshold: f64,
+    pub offset: f64,
+}
+
+impl Loudness {
+    /// Second pass `loudnorm` filter bringing the measured audio to the target loudness
+    pub fn normalise_filter(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP=-1.5:LRA=11:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+            *LOUDNESS_TARGET,
+            self.integrated,
+            self.true_peak,
+            self.range,
+            self.threshold,
+            self.offset
+        )
+    }
+
+    /// Silent audio measures as -inf, and can't be normalised
+    pub fn is_measurable(&self) -> bool {
+        self.integrated.is_finite() && self.true_peak.is_finite()
+    }
+
+    /// Audio that is too short to measure has no integrated loudness but still has a peak. Only
+    /// audio with no peak at all is silent
+    pub fn is_silent(&self) -> bool {
+        !self.true_peak.is_finite()
+    }
+}
+
+/// Optional trimming applied to an upload. All times are in seconds
+#[derive(Default)]
+pub struct Trim {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub fade_out: Option<f64>,
+}
+
+impl Trim {
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.end.is_none() && self.fade_out.is_none()
+    }
+
+    /// Build the filter graph trimming a source of the given duration, returning the filter
+    /// and the duration of the trimmed audio. Bad ranges return a message for the user
+    pub fn filter(&self, duration: f64) -> Result<(Option<String>, f64), String> {
+        let start = self.start.unwrap_or(0.0);
+        let end = self.end.map_or(duration, |e| e.min(duration));
+
+        if start < 0.0 {
+            return Err("The start time cannot be negative.".to_string());
+        }
+        if start >= duration {
+            return Err(format!(
+                "The start time ({}s) is past the end of the file ({:.2}s).",
+                start, duration
+            ));
+        }
+        if let Some(end) = self.end {
+            if end <= start {
+                return Err(format!(
+                    "The end time ({}s) must be after the start time ({}s).",
+                    end, start
+                ));
+            }
+        }
+
+        let length = end - start;
+        let mut filters = vec![];
+
+        if self.start.is_some() || self.end.is_some() {
+            filters.push(format!(
+                "atrim=start={}:end={},asetpts=PTS-STARTPTS",
+                start, end
+            ));
+        }
+
+        if let Some(fade_out) = self.fade_out {
+            if fade_out <= 0.0 {
+                return Err("The fade out must be longer than 0 seconds.".to_string());
+            }
+            if fade_out > length {
+                return Err(format!(
+                    "The fade out ({}s) is longer than the sound ({:.2}s).",
+                    fade_out, length
+                ));
+            }
+
+            filters.push(format!(
+                "afade=t=out:st={}:d={}",
+                length - fade_out,
+                fade_out
+            ));
+        }
+
+        if filters.is_empty() {
+            Ok((None, length))
+        } else {
+            Ok((Some(filters.join(",")), length))
+        }
+    }
+}
+
+fn chain_filters(first: Option<&str>, second: Option<&str>) -> Option<String> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(format!("{},{}", first, second)),
+
+        (first, second) => first.or(second).map(|f| f.to_string()),
+    }
+}
+
+/// Effects applied to a sound at playback time
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Effects {
+    /// Playback speed multiplier, preserving pitch
+    pub speed: Option<f64>,
+    /// Pitch shift in semitones, preserving speed
+    pub pitch: Option<f64>,
+    pub reverse: bool,
+    pub echo: bool,
+    /// Low-pass cutoff frequency in Hz
+    pub lowpass: Option<u32>,
+    /// Bass gain in dB
+    pub bass_boost: Option<f64>,
+    /// Linear gain applied ahead of a limiter, for boosting past full volume without
+    /// clipping. Set from the sound's gain rather than parsed from user input
+    pub gain: Option<f64>,
+}
+
+impl Effects {
+    pub fn is_empty(&self) -> bool {
+        *self == Effects::default()
+    }
+
+    /// Build the ffmpeg filter graph for these effects. Stored audio is always 48kHz
+    pub fn filter(&self) -> Option<String> {
+        let mut filters = vec![];
+
+        if self.reverse {
+            filters.push("areverse".to_string());
+        }
+
+        if let Some(pitch) = self.pitch {
+            let factor = 2f64.powf(pitch / 12.0);
+
+            filters.push(format!(
+                "asetrate={},aresample=48000,atempo={}",
+                48000.0 * factor,
+                1.0 / factor
+            ));
+        }
+
+        if let Some(mut speed) = self.speed {
+            // older builds of atempo only accept factors between 0.5 and 2
+            while speed > 2.0 {
+                filters.push("atempo=2".to_string());
+                speed /= 2.0;
+            }
+            while speed < 0.5 {
+                filters.push("atempo=0.5".to_string());
+                speed /= 0.5;
+            }
+
+            filters.push(format!("atempo={}", speed));
+        }
+
+        if let Some(lowpass) = self.lowpass {
+            filters.push(format!("lowpass=f={}", lowpass));
+        }
+
+        if let Some(bass_boost) = self.bass_boost {
+            filters.push(format!("bass=g={}", bass_boost));
+        }
+
+        if self.echo {
+            filters.push("aecho=0.8:0.88:60|400:0.4|0.25".to_string());
+        }
+
+        // last, so that peaks introduced by the other effects are limited too
+        if let Some(gain) = self.gain {
+            filters.push(format!("volume={:.2},alimiter=limit=1:level=false", gain));
+        }
+
+        if filters.is_empty() {
+            None
+        } else {
+            Some(filters.join(","))
+        }
+    }
+
+    /// Short stable identifier for caching processed variants of a sound
+    pub fn hash(&self) -> Option<String> {
+        self.filter().map(|filter| {
+            let digest = format!("{:x}", Sha256::digest(filter.as_bytes()));
+
+            digest[..16].to_string()
+        })
+    }
+}
+
+impl FromStr for Effects {
+    type Err = String;
+
+    /// Parse effects written like `speed=1.5 pitch=-2 reverse echo lowpass=800 bass=6`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn value<T: FromStr>(name: &str, value: Option<&str>) -> Result<T, String> {
+            value
+                .ok_or_else(|| format!("The `{}` effect needs a value, like `{}=1`", name, name))?
+                .parse::<T>()
+                .map_err(|_| format!("The value given for `{}` is not a number", name))
+        }
+
+        fn check_range(name: &str, value: f64, min: f64, max: f64) -> Result<(), String> {
+            if value < min || value > max || !value.is_finite() {
+                Err(format!(
+                    "The `{}` effect must be between {} and {}",
+                    name, min, max
+                ))
+            } else {
+                Ok(())
+            }
+        }
+
+        let mut effects = Effects::default();
+
+        for token in s.split(|c: char| c.is_whitespace() || c == ',') {
+            if token.is_empty() {
+                continue;
+            }
+
+            let mut parts = token.splitn(2, |c| c == '=' || c == ':');
+            let name = parts.next().unwrap_or_default().to_lowercase();
+            let arg = parts.next();
+
+            match name.as_str() {
+                "speed" => {
+                    let speed = value(&name, arg)?;
+                    check_range(&name, speed, 0.25, 4.0)?;
+
+                    effects.speed = Some(speed);
+                }
+
+                "pitch" => {
+                    let pitch = value(&name, arg)?;
+                    check_range(&name, pitch, -12.0, 12.0)?;
+
+                    effects.pitch = Some(pitch);
+                }
+
+                "reverse" => effects.reverse = true,
+
+                "echo" => effects.echo = true,
+
+                "lowpass" => {
+                    let lowpass = value::<u32>(&name, arg)?;
+                    check_range(&name, lowpass as f64, 100.0, 20000.0)?;
+
+                    effects.lowpass = Some(lowpass);
+                }
+
+                "bass" | "bassboost" => {
+                    let bass_boost = value(&name, arg)?;
+                    check_range(&name, bass_boost, 0.0, 20.0)?;
+
+                    effects.bass_boost = Some(bass_boost);
+                }
+
+                _ => {
+                    return Err(format!(
+                        "Unknown effect `{}`. Available effects are `speed`, `pitch`, `reverse`, `echo`, `lowpass` and `bass`",
+                        token
+                    ));
+                }
+            }
+        }
+
+        Ok(effects)
+    }
+}
+
+#[derive(poise::ChoiceParameter)]
+pub enum DownloadFormat {
+    #[name = "Opus (original)"]
+    Opus,
+    #[name = "MP3"]
+    Mp3,
+    #[name = "WAV"]
+    Wav,
+    #[name = "Ogg Vorbis"]
+    Vorbis,
+    #[name = "FLAC"]
+    Flac,
+}
+
+impl DownloadFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DownloadFormat::Opus => "opus",
+            DownloadFormat::Mp3 => "mp3",
+            DownloadFormat::Wav => "wav",
+            DownloadFormat::Vorbis => "ogg",
+            DownloadFormat::Flac => "flac",
+        }
+    }
+
+    /// ffmpeg output arguments for the format, or `None` if the stored file can be sent as-is
+    pub fn output_args(&self) -> Option<&'static [&'static str]> {
+        match self {
+            DownloadFormat::Opus => None,
+            DownloadFormat::Mp3 => Some(&["-f", "mp3", "-codec:a", "libmp3lame", "-q:a", "2"]),
+            DownloadFormat::Wav => Some(&["-f", "wav"]),
+            DownloadFormat::Vorbis => Some(&["-f", "ogg", "-codec:a", "libvorbis", "-q:a", "5"]),
+            DownloadFormat::Flac => Some(&["-f", "flac"]),
+        }
+    }
+}
+
+/// The last few lines of ffmpeg's stderr, where the error that stopped it is reported
+fn stderr_tail(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let mut lines = stderr
+        .lines()
+        .rev()
+        .filter(|l| !l.trim().is_empty())
+        .take(5)
+        .collect::<Vec<_>>();
+    lines.reverse();
+
+    lines.join("\n")
+}
+
+/// Work out why a run of ffmpeg or ffprobe failed from its stderr
+fn command_error(src: &str, output: &Output) -> ErrorTypes {
+    let stderr = stderr_tail(&output.stderr);
+    let lower = stderr.to_lowercase();
+
+    if src.starts_with("http")
+        && [
+            "http error",
+            "server returned",
+            "connection",
+            "failed to resolve",
+        ]
+        .iter()
+        .any(|m| lower.contains(m))
+    {
+        ErrorTypes::DownloadFailed {
+            url: src.to_string(),
+            details: stderr,
+        }
+    } else if [
+        "invalid data found",
+        "could not find codec",
+        "unsupported codec",
+        "decoder not found",
+        "does not contain any stream",
+    ]
+    .iter()
+    .any(|m| lower.contains(m))
+    {
+        ErrorTypes::UnsupportedCodec(stderr)
+    } else {
+        ErrorTypes::FfmpegFailed {
+            status: output.status.code(),
+            stderr,
+        }
+    }
+}
+
+async fn run(command: &mut Command, src: &str) -> Result<Output, ErrorTypes> {
+    // failing to start at all usually means ffmpeg isn't installed
+    let spawn_error = |e: std::io::Error| ErrorTypes::FfmpegFailed {
+        status: None,
+        stderr: e.to_string(),
+    };
+    let output = command
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(spawn_error)?;
+
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(command_error(src, &output))
+    }
+}
+
+/// Stream and container information reported by ffprobe
+pub struct Metadata {
+    /// Unknown for sources without a container duration, such as streamed Ogg files
+    pub duration: Option<f64>,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub bitrate: u32,
+}
+
+/// Probe an audio file or URL for its duration, channel count, sample rate and bitrate
+pub async fn probe(src: &str) -> Result<Metadata, ErrorTypes> {
+    #[derive(Deserialize)]
+    struct ProbeStream {
+        channels: u32,
+        sample_rate: String,
+    }
+
+    #[derive(Deserialize)]
+    struct ProbeFormat {
+        duration: Option<String>,
+        bit_rate: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct ProbeOutput {
+        streams: Vec<ProbeStream>,
+        format: ProbeFormat,
+    }
+
+    let output = run(
+        Command::new("ffprobe")
+            .arg("-v")
+            .arg("error")
+            .arg("-select_streams")
+            .arg("a:0")
+            .arg("-show_entries")
+            .arg("format=duration,bit_rate:stream=channels,sample_rate")
+            .arg("-of")
+            .arg("json")
+            .arg(src),
+        src,
+    )
+    .await?;
+
+    let unreadable = |reason: &str| ErrorTypes::UnsupportedCodec(reason.to_string());
+
+    let probe = serde_json::from_slice::<ProbeOutput>(&output.stdout)
+        .map_err(|_| unreadable("ffprobe output couldn't be read"))?;
+    let stream = probe
+        .streams
+        .first()
+        .ok_or_else(|| unreadable("no audio stream"))?;
+
+    Ok(Metadata {
+        duration: probe.format.duration.and_then(|d| d.parse().ok()),
+        channels: stream.channels,
+        sample_rate: stream
+            .sample_rate
+            .parse()
+            .map_err(|_| unreadable("unknown sample rate"))?,
+        bitrate: probe
+            .format
+            .bit_rate
+            .and_then(|b| b.parse().ok())
+            .unwrap_or(0),
+    })
+}
+
+/// Format a duration in seconds for display, e.g. `4.2s` or `1:05`
+pub fn format_duration(seconds: f64) -> String {
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else {
+        format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60)
+    }
+}
+
+/// Measure the EBU R128 integrated loudness and true peak of an audio file or URL, after
+/// applying an optional filter graph
+pub async fn measure_loudness(src: &str, filter: Option<&str>) -> Result<Loudness, ErrorTypes> {
+    #[derive(Deserialize)]
+    struct LoudnormOutput {
+        input_i: String,
+        input_tp: String,
+        input_lra: String,
+        input_thresh: String,
+        target_offset: String,
+    }
+
+    let loudnorm = format!(
+        "loudnorm=I={}:TP=-1.5:LRA=11:print_format=json",
+        *LOUDNESS_TARGET
+    );
+
+    let output = run(
+        Command::new("ffmpeg")
+            .arg("-hide_banner")
+            .arg("-nostats")
+            .arg("-i")
+            .arg(src)
+            .arg("-af")
+            .arg(chain_filters(filter, Some(&loudnorm)).unwrap_or(loudnorm))
+            .arg("-f")
+            .arg("null")
+            .arg("-"),
+        src,
+    )
+    .await?;
+
+    let unexpected = || ErrorTypes::FfmpegFailed {
+        status: output.status.code(),
+        stderr: stderr_tail(&output.stderr),
+    };
+
+    // the filter prints its statistics as the last JSON object in stderr
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let (start, end) = match (stderr.rfind('{'), stderr.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+
+        _ => return Err(unexpected()),
+    };
+    let stats =
+        serde_json::from_str::<LoudnormOutput>(&stderr[start..=end]).map_err(|_| unexpected())?;
+
+    // silent audio measures as "-inf", which parses as negative infinity
+    let parse = |value: &str| value.parse::<f64>().map_err(|_| unexpected());
+
+    Ok(Loudness {
+        integrated: parse(&stats.input_i)?,
+        true_peak: parse(&stats.input_tp)?,
+        range: parse(&stats.input_lra)?,
+        threshold: parse(&stats.input_thresh)?,
+        offset: parse(&stats.target_offset)?,
+    })
+}
+
+/// Transcode an audio file or URL, applying an optional filter graph. `output_args` select the
+/// output format, and output reaching `max_size` bytes is an error
+pub async fn transcode(
+    src: &str,
+    filter: Option<&str>,
+    output_args: &[&str],
+    max_size: Option<u64>,
+) -> Result<Vec<u8>, ErrorTypes> {
+    let mut command = Command::new("ffmpeg");
+
+    command.arg("-i").arg(src).arg("-loglevel").arg("error");
+
+    if let Some(filter) = filter {
+        command.arg("-af").arg(filter);
+    }
+
+    command.args(output_args);
+
+    // ffmpeg stops writing once the output reaches the limit, so it is never much larger
+    if let Some(max_size) = max_size {
+        command.arg("-fs").arg(max_size.to_string());
+    }
+
+    let output = run(command.arg("pipe:1"), src).await?;
+
+    match max_size {
+        Some(max_size) if output.stdout.len() as u64 >= max_size => {
+            Err(ErrorTypes::TooLarge { max_size })
+        }
+
+        _ => Ok(output.stdout),
+    }
+}
+
+/// Transcode an audio file or URL to opus for storage, applying an optional filter graph
+pub async fn encode_opus(
+    src: &str,
+    filter: Option<&str>,
+    max_size: Option<u64>,
+) -> Result<Vec<u8>, ErrorTypes> {
+    // bitexact output is reproducible, so identical uploads can be deduplicated by hash
+    transcode(
+        src,
+        filter,
+        &[
+            "-ar",
+            "48000",
+            "-fflags",
+            "+bitexact",
+            "-flags:a",
+            "+bitexact",
+            "-f",
+            "opus",
+        ],
+        max_size,
+    )
+    .await
+}
+
+/// Measure and normalise an audio file or URL after applying an optional filter graph,
+/// returning the opus output and the loudness measured before normalisation. Audio that can't
+/// be measured, because it is silent or very short, is encoded without normalising
+pub async fn normalise_opus(
+    src: &str,
+    filter: Option<&str>,
+    max_size: Option<u64>,
+) -> Result<(Vec<u8>, Loudness), ErrorTypes> {
+    let loudness = measure_loudness(src, filter).await?;
+    let normalise_filter = Some(&loudness)
+        .filter(|l| l.is_measurable())
+        .map(|l| l.normalise_filter());
+    let filter = chain_filters(filter, normalise_filter.as_deref());
+
+    let data = encode_opus(src, filter.as_deref(), max_size).await?;
+
+    Ok((data, loudness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trim(start: Option<f64>, end: Option<f64>, fade_out: Option<f64>) -> Trim {
+        Trim {
+            start,
+            end,
+            fade_out,
+        }
+    }
+
+    #[test]
+    fn trim_empty() {
+        assert!(Trim::default().is_empty());
+        assert_eq!(Trim::default().filter(10.0), Ok((None, 10.0)));
+    }
+
+    #[test]
+    fn trim_start_and_end() {
+        assert_eq!(
+            trim(Some(1.0), Some(3.5), None).filter(10.0),
+            Ok((
+                Some("atrim=start=1:end=3.5,asetpts=PTS-STARTPTS".to_string()),
+                2.5
+            ))
+        );
+    }
+
+    #[test]
+    fn trim_end_past_duration() {
+        assert_eq!(
+            trim(None, Some(20.0), None).filter(10.0),
+            Ok((
+                Some("atrim=start=0:end=10,asetpts=PTS-STARTPTS".to_string()),
+                10.0
+            ))
+        );
+    }
+
+    #[test]
+    fn trim_fade_out() {
+        assert_eq!(
+            trim(None, None, Some(2.0)).filter(10.0),
+            Ok((Some("afade=t=out:st=8:d=2".to_string()), 10.0))
+        );
+        assert_eq!(
+            trim(Some(4.0), None, Some(2.0)).filter(10.0),
+            Ok((
+                Some("atrim=start=4:end=10,asetpts=PTS-STARTPTS,afade=t=out:st=4:d=2".to_string()),
+                6.0
+            ))
+        );
+    }
+
+    #[test]
+    fn trim_invalid() {
+        assert!(trim(Some(-1.0), None, None).filter(10.0).is_err());
+        assert!(trim(Some(10.0), None, None).filter(10.0).is_err());
+        assert!(trim(Some(3.0), Some(3.0), None).filter(10.0).is_err());
+        assert!(trim(Some(3.0), Some(2.0), None).filter(10.0).is_err());
+        assert!(trim(None, None, Some(0.0)).filter(10.0).is_err());
+        assert!(trim(Some(8.0), None, Some(3.0)).filter(10.0).is_err());
+    }
+
+    #[test]
+    fn effects_parse() {
+        let effects = "speed=1.5 pitch=-2 reverse echo lowpass=800 bass=6"
+            .parse::<Effects>()
+            .unwrap();
+
+        assert_eq!(
+            effects,
+            Effects {
+                speed: Some(1.5),
+                pitch: Some(-2.0),
+                reverse: true,
+                echo: true,
+                lowpass: Some(800),
+                bass_boost: Some(6.0),
+                gain: None,
+            }
+        );
+    }
+
+    #[test]
+    fn effects_parse_separators() {
+        let effects = "SPEED:2, bassboost=3".parse::<Effects>().unwrap();
+
+        assert_eq!(effects.speed, Some(2.0));
+        assert_eq!(effects.bass_boost, Some(3.0));
+        assert!("  ".parse::<Effects>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn effects_parse_invalid() {
+        assert!("speed".parse::<Effects>().is_err());
+        assert!("speed=fast".parse::<Effects>().is_err());
+        assert!("speed=5".parse::<Effects>().is_err());
+        assert!("pitch=NaN".parse::<Effects>().is_err());
+        assert!("lowpass=50".parse::<Effects>().is_err());
+        assert!("lowpass=-800".parse::<Effects>().is_err());
+        assert!("flange".parse::<Effects>().is_err());
+    }
+
+    #[test]
+    fn effects_filter_empty() {
+        assert_eq!(Effects::default().filter(), None);
+        assert_eq!(Effects::default().hash(), None);
+    }
+
+    #[test]
+    fn effects_filter_order() {
+        let effects = Effects {
+            reverse: true,
+            echo: true,
+            lowpass: Some(800),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            effects.filter().unwrap(),
+            "areverse,lowpass=f=800,aecho=0.8:0.88:60|400:0.4|0.25"
+        );
+    }
+
+    #[test]
+    fn effects_filter_pitch() {
+        let effects = Effects {
+            pitch: Some(12.0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            effects.filter().unwrap(),
+            "asetrate=96000,aresample=48000,atempo=0.5"
+        );
+    }
+
+    #[test]
+    fn effects_filter_speed_chains_atempo() {
+        let fast = Effects {
+            speed: Some(4.0),
+            ..Default::default()
+        };
+        let slow = Effects {
+            speed: Some(0.25),
+            ..Default::default()
+        };
+
+        assert_eq!(fast.filter().unwrap(), "atempo=2,atempo=2");
+        assert_eq!(slow.filter().unwrap(), "atempo=0.5,atempo=0.5");
+    }
+
+    #[test]
+    fn effects_hash_depends_on_filter() {
+        let echo = Effects {
+            echo: true,
+            ..Default::default()
+        };
+        let reverse = Effects {
+            reverse: true,
+            ..Default::default()
+        };
+
+        assert_eq!(echo.hash(), echo.clone().hash());
+        assert_ne!(echo.hash(), reverse.hash());
+        assert_eq!(echo.hash().unwrap().len(), 16);
+    }
+}
@@ -0,0 +1,78 @@
+use log::{info, warn};
+
+use crate::{models::sound::Sound, Context, Error};
+
+/// Re-normalise the loudness of all sounds uploaded before normalisation was introduced
+#[poise::command(
+    slash_command,
+    rename = "renormalise",
+    owners_only = true,
+    hide_in_help
+)]
+pub async fn renormalise_sounds(ctx: Context<'_>) -> Result<(), Error> {
+    let sounds = sqlx::query_as_unchecked!(
+        Sound,
+        "
+SELECT name, id, public, server_id, uploader_id, duration, gain
+    FROM sounds
+    WHERE normalised = 0
+        "
+    )
+    .fetch_all(&ctx.data().database)
+    .await?;
+
+    ctx.send(|m| {
+        m.ephemeral(true).content(format!(
+            "Re-normalising {} sounds in the background",
+            sounds.len()
+        ))
+    })
+    .await?;
+
+    let data = ctx.data().clone();
+    tokio::spawn(async move {
+        let mut failed = 0;
+
+        for sound in &sounds {
+            if let Err(e) = sound.normalise(&data).await {
+                warn!("Failed to normalise sound {}: {:?}", sound.id, e);
+
+                failed += 1;
+            }
+        }
+
+        info!(
+            "Finished re-normalising {} sounds ({} failed)",
+            sounds.len(),
+            failed
+        );
+    });
+
+    Ok(())
+}
+
+/// View how effectively frequently played sounds are being held in memory
+#[poise::command(slash_command, rename = "cachestats", owners_only = true, hide_in_help)]
+pub async fn cache_stats(ctx: Context<'_>) -> Result<(), Error> {
+    let stats = ctx.data().memory_cache.stats();
+
+    ctx.send(|m| {
+        m.ephemeral(true).content(format!(
+            "**Memory cache**
+Hit rate: {:.1}% ({} hits, {} misses)
+Entries: {} ({:.1}MB of {:.1}MB)
+Admissions: {}, evictions: {}",
+            stats.hit_rate() * 100.0,
+            stats.hits,
+            stats.misses,
+            stats.entries,
+            stats.size as f64 / 1_048_576.0,
+            stats.max_size as f64 / 1_048_576.0,
+            stats.admissions,
+            stats.evictions
+        ))
+    })
+    .await?;
+
+    Ok(())
+}
@@ -1,4 +1,7 @@
-use crate::{consts::THEME_COLOR, Context, Error};
+use crate::{
+    audio::format_duration, cmds::autocomplete_sound, consts::THEME_COLOR, models::sound::SoundCtx,
+    Context, Error,
+};
 
 /// View bot commands
 #[poise::command(slash_command)]
@@ -20,22 +23,38 @@ pub async fn help(ctx: Context<'_>) -> Result<(), Error> {
 *run these commands with no options*
 
 __Play Commands__
-`/play` - Play a sound by name or ID
-`/queue` - Play sounds on queue instead of instantly
-`/loop` - Play a sound on loop
+`/play` - Play a sound by name or ID. Set `panel` to show what's playing with playback controls
+*`/play a > b` plays sounds one after another, and `/play a + b` plays them at the same time*
+*`/play` and `/loop` accept `start` and `duration` to play only part of a sound*
+`/queue add` - Queue up to 24 sounds instead of playing them instantly
+`/queue view` - View the sounds in the queue
+`/queue skip/remove/move/shuffle/clear` - Change the sounds in the queue
+`/queue pause/resume` - Pause or resume the queue
+`/loop` - Play a sound on loop, optionally a number of times or for a length of time
+`/endloop` - Stop looping sounds without stopping other sounds
+`/playlist play` - Queue the sounds in a saved playlist
+`/playlist create/add/remove/move/delete/list` - Manage your playlists and this server's
+`/schedule add/list/cancel` - Play sounds at a set time or on repeat
+*`/play`, `/queue add` and `/loop` accept effects, e.g. `speed=1.5 pitch=-2 reverse echo lowpass=800 bass=6`*
 `/disconnect` - Disconnect the bot
 `/stop` - Stop playback
 
 __Library Commands__
 `/upload` - Upload a sound file
+`/bulkupload` - Upload a zip archive of sound files
+`/replace` - Replace the file of a sound you uploaded
 `/delete` - Delete a sound file
 `/download` - Download a sound file
+`/details` - View details of a sound
 `/public` - Set a sound as public/private
+`/gain` - Make a sound you uploaded louder or quieter
+`/tag add/remove` - Tag a sound you uploaded
+`/alias add/remove` - Let a sound you uploaded be played by another name
 `/list server` - List sounds on this server
 `/list user` - List your sounds
 
 __Search Commands__
-`/search` - Search for public sounds by name
+`/search` - Search for public sounds by name. Add `tag:name` to only show sounds with a tag
 `/random` - View random public sounds
 
 __Setting Commands__
@@ -43,6 +62,11 @@ __Setting Commands__
 `/greet user set/unset` - Set or unset a join sound across all servers
 `/greet enable/disable` - Enable or disable join sounds on this server
 `/volume` - Change the volume
+`/maxduration` - Limit the length of sounds played on this server
+`/playbackmode` - Choose whether new sounds overlap, interrupt or queue behind playing sounds
+`/idletimeout` - Leave voice after a length of time without playing
+`/ratelimit set/disable` - Limit how often each user can play sounds
+`/ratelimit exempt/unexempt` - Choose roles that aren't rate limited
 
 __Advanced Commands__
 `/soundboard` - Create a soundboard",
@@ -77,3 +101,97 @@ There is a maximum sound limit per user. This can be removed by subscribing at *
 
     Ok(())
 }
+
+/// Show details about a sound
+#[poise::command(slash_command, rename = "details", guild_only = true)]
+pub async fn sound_details(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to show details of"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+) -> Result<(), Error> {
+    let sound_vec = ctx
+        .data()
+        .search_for_sound(&name, ctx.guild_id().unwrap(), ctx.author().id, true)
+        .await?;
+
+    match sound_vec.first() {
+        Some(sound) => {
+            let metadata = sound.metadata(&ctx.data().database).await?;
+            let tags = sound.tags(&ctx.data().database).await?;
+            let aliases = sound.aliases(&ctx.data().database).await?;
+
+            let unknown = || "Unknown".to_string();
+
+            ctx.send(|m| {
+                m.embed(|e| {
+                    e.title(&sound.name)
+                        .color(THEME_COLOR)
+                        .field("ID", sound.id, true)
+                        .field(
+                            "Visibility",
+                            if sound.public { "Public" } else { "Private" },
+                            true,
+                        )
+                        .field(
+                            "Duration",
+                            metadata.duration.map_or_else(unknown, format_duration),
+                            true,
+                        )
+                        .field("Gain", format!("{:.2}x", sound.gain), true)
+                        .field(
+                            "Channels",
+                            metadata.channels.map_or_else(unknown, |c| match c {
+                                1 => "Mono".to_string(),
+                                2 => "Stereo".to_string(),
+                                c => c.to_string(),
+                            }),
+                            true,
+                        )
+                        .field(
+                            "Sample rate",
+                            metadata
+                                .sample_rate
+                                .map_or_else(unknown, |r| format!("{}Hz", r)),
+                            true,
+                        )
+                        .field(
+                            "Bitrate",
+                            metadata
+                                .bitrate
+                                .map_or_else(unknown, |b| format!("{}kbps", b / 1000)),
+                            true,
+                        )
+                        .field(
+                            "Tags",
+                            if tags.is_empty() {
+                                "None".to_string()
+                            } else {
+                                tags.iter()
+                                    .map(|t| format!("`{}`", t))
+                                    .collect::<Vec<_>>()
+                                    .join(" ")
+                            },
+                            false,
+                        )
+                        .field(
+                            "Aliases",
+                            if aliases.is_empty() {
+                                "None".to_string()
+                            } else {
+                                aliases.join(", ")
+                            },
+                            false,
+                        )
+                })
+            })
+            .await?;
+        }
+
+        None => {
+            ctx.say("No sound found by specified name/ID").await?;
+        }
+    }
+
+    Ok(())
+}
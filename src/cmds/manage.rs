@@ -1,13 +1,62 @@
-use poise::serenity_prelude::{Attachment, GuildId, RoleId};
+use std::{
+    collections::HashSet,
+    env,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use poise::serenity_prelude::{
+    constants::MESSAGE_CODE_LIMIT, Attachment, GuildId, PremiumTier, RoleId,
+};
 use tokio::fs::File;
+use zip::ZipArchive;
 
 use crate::{
+    audio::{format_duration, DownloadFormat, Trim},
     cmds::autocomplete_sound,
     consts::{MAX_SOUNDS, PATREON_GUILD, PATREON_ROLE},
-    models::sound::{Sound, SoundCtx},
+    error,
+    models::sound::{normalise_tag, Sound, SoundCtx},
     Context, Error,
 };
 
+const BULK_MAX_ENTRIES: usize = 50;
+const BULK_MAX_ENTRY_SIZE: u64 = 25 * 1024 * 1024;
+const BULK_AUDIO_EXTENSIONS: [&str; 10] = [
+    "mp3", "wav", "ogg", "opus", "flac", "m4a", "aac", "webm", "mp4", "wma",
+];
+
+fn is_numeric(s: &String) -> bool {
+    for char in s.chars() {
+        if char.is_digit(10) {
+            continue;
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+fn upload_length(duration: Option<f64>) -> String {
+    duration.map_or_else(|| "unknown length".to_string(), format_duration)
+}
+
+async fn is_patreon(ctx: Context<'_>) -> bool {
+    let patreon_guild_member = GuildId(*PATREON_GUILD)
+        .member(ctx.discord(), ctx.author().id)
+        .await;
+
+    if let Ok(member) = patreon_guild_member {
+        member.roles.contains(&RoleId(*PATREON_ROLE))
+    } else {
+        false
+    }
+}
+
+fn upload_error_message(e: &Error) -> String {
+    format!("Sound failed to upload. {}", error::user_message(e))
+}
+
 /// Upload a new sound to the bot
 #[poise::command(
     slash_command,
@@ -20,20 +69,12 @@ pub async fn upload_new_sound(
     ctx: Context<'_>,
     #[description = "Name to upload sound to"] name: String,
     #[description = "Sound file (max. 2MB)"] file: Attachment,
+    #[description = "Time in seconds to start the sound from"] start: Option<f64>,
+    #[description = "Time in seconds to end the sound at"] end: Option<f64>,
+    #[description = "Seconds to fade out over at the end"] fade_out: Option<f64>,
 ) -> Result<(), Error> {
     ctx.defer().await?;
 
-    fn is_numeric(s: &String) -> bool {
-        for char in s.chars() {
-            if char.is_digit(10) {
-                continue;
-            } else {
-                return false;
-            }
-        }
-        true
-    }
-
     if !name.is_empty() && name.len() <= 20 {
         if !is_numeric(&name) {
             // need to check the name is not currently in use by the user
@@ -48,38 +89,39 @@ pub async fn upload_new_sound(
             } else {
                 // need to check how many sounds user currently has
                 let count = Sound::count_user_sounds(ctx.author().id, &ctx.data().database).await?;
-                let mut permit_upload = true;
 
                 // need to check if user is patreon or nah
-                if count >= *MAX_SOUNDS {
-                    let patreon_guild_member = GuildId(*PATREON_GUILD)
-                        .member(ctx.discord(), ctx.author().id)
-                        .await;
-
-                    if let Ok(member) = patreon_guild_member {
-                        permit_upload = member.roles.contains(&RoleId(*PATREON_ROLE));
-                    } else {
-                        permit_upload = false;
-                    }
-                }
+                let permit_upload = count < *MAX_SOUNDS || is_patreon(ctx).await;
 
                 if permit_upload {
+                    let trim = Trim {
+                        start,
+                        end,
+                        fade_out,
+                    };
+
                     match Sound::create_anon(
                         &name,
                         file.url.as_str(),
+                        &trim,
                         ctx.guild_id().unwrap(),
                         ctx.author().id,
-                        &ctx.data().database,
+                        ctx.data(),
                     )
                     .await
                     {
-                        Ok(_) => {
-                            ctx.say("Sound has been uploaded").await?;
+                        Ok(upload) => {
+                            ctx.say(format!(
+                                "Sound has been uploaded with ID {} ({}, {:.1}KB)",
+                                upload.id,
+                                upload_length(upload.duration),
+                                upload.size as f64 / 1024.0
+                            ))
+                            .await?;
                         }
 
                         Err(e) => {
-                            println!("Error occurred during upload: {:?}", e);
-                            ctx.say("Sound failed to upload.").await?;
+                            ctx.say(upload_error_message(&e)).await?;
                         }
                     }
                 } else {
@@ -100,6 +142,246 @@ pub async fn upload_new_sound(
     Ok(())
 }
 
+struct BulkEntry {
+    name: String,
+    path: Option<PathBuf>,
+}
+
+/// Extract the audio files of a zip archive into a directory. Entries that are too large are
+/// returned without a path
+fn extract_audio_entries(archive: Vec<u8>, dir: &Path) -> Result<Vec<BulkEntry>, Error> {
+    let mut zip = ZipArchive::new(Cursor::new(archive))?;
+    let mut entries = vec![];
+
+    std::fs::create_dir_all(dir)?;
+
+    for index in 0..zip.len() {
+        let file = zip.by_index(index)?;
+
+        // `enclosed_name` rejects paths that would escape the extraction directory
+        let entry_path = match file.enclosed_name() {
+            Some(path) if file.is_file() => path.to_path_buf(),
+
+            _ => continue,
+        };
+
+        if entry_path.components().any(|c| c.as_os_str() == "__MACOSX") {
+            continue;
+        }
+
+        let extension = entry_path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !BULK_AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            continue;
+        }
+
+        if entries.len() >= BULK_MAX_ENTRIES {
+            return Err(format!(
+                "The archive contains more than {} audio files.",
+                BULK_MAX_ENTRIES
+            )
+            .into());
+        }
+
+        let name = entry_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().trim().to_string())
+            .unwrap_or_default();
+
+        if file.size() > BULK_MAX_ENTRY_SIZE {
+            entries.push(BulkEntry { name, path: None });
+            continue;
+        }
+
+        let out_path = dir.join(format!("{}.{}", index, extension));
+        let mut out_file = std::fs::File::create(&out_path)?;
+
+        // the declared size can't be trusted, so limit the amount actually decompressed
+        std::io::copy(&mut file.take(BULK_MAX_ENTRY_SIZE), &mut out_file)?;
+
+        entries.push(BulkEntry {
+            name,
+            path: Some(out_path),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Upload many sounds at once from a zip archive, named after each file
+#[poise::command(
+    slash_command,
+    rename = "bulkupload",
+    category = "Manage",
+    default_member_permissions = "MANAGE_GUILD",
+    guild_only = true
+)]
+pub async fn bulk_upload(
+    ctx: Context<'_>,
+    #[description = "Zip archive of sound files"] archive: Attachment,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    if !archive.filename.to_lowercase().ends_with(".zip") {
+        ctx.say("Please attach a `.zip` archive of sound files.")
+            .await?;
+
+        return Ok(());
+    }
+
+    let dir = env::temp_dir().join(format!("soundfx-bulk-{}", ctx.id()));
+    let bytes = archive.download().await?;
+
+    let entries = {
+        let dir = dir.clone();
+
+        tokio::task::spawn_blocking(move || extract_audio_entries(bytes, &dir)).await?
+    };
+
+    let entries = match entries {
+        Ok(entries) => entries,
+
+        Err(e) => {
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+            ctx.say(format!("Couldn't read the archive. {}", e)).await?;
+
+            return Ok(());
+        }
+    };
+
+    if entries.is_empty() {
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        ctx.say("The archive doesn't contain any sound files.")
+            .await?;
+
+        return Ok(());
+    }
+
+    let mut count = Sound::count_user_sounds(ctx.author().id, &ctx.data().database).await?;
+    // only ask the Patreon guild if this upload could take the user over the limit
+    let unlimited = count + entries.len() as u32 <= *MAX_SOUNDS || is_patreon(ctx).await;
+    let mut used_names = HashSet::new();
+    let mut report = vec![];
+
+    for entry in entries {
+        let result = if entry.name.is_empty() || entry.name.len() > 20 {
+            Err("name must be between 1 and 20 characters".to_string())
+        } else if is_numeric(&entry.name) {
+            Err("name must contain a non-numerical character".to_string())
+        } else if !used_names.insert(entry.name.clone())
+            || Sound::count_named_user_sounds(ctx.author().id, &entry.name, &ctx.data().database)
+                .await
+                .map_or(true, |c| c > 0)
+        {
+            Err("you are already using that name".to_string())
+        } else if count >= *MAX_SOUNDS && !unlimited {
+            Err(format!(
+                "you have reached the maximum number of sounds ({})",
+                *MAX_SOUNDS
+            ))
+        } else {
+            match &entry.path {
+                Some(path) => Sound::create_anon(
+                    &entry.name,
+                    &path.to_string_lossy(),
+                    &Trim::default(),
+                    ctx.guild_id().unwrap(),
+                    ctx.author().id,
+                    ctx.data(),
+                )
+                .await
+                .map(|upload| {
+                    count += 1;
+
+                    format!(
+                        "uploaded with ID {} ({})",
+                        upload.id,
+                        upload_length(upload.duration)
+                    )
+                })
+                .map_err(|e| error::user_message(&e)),
+
+                None => Err("file is too large".to_string()),
+            }
+        };
+
+        report.push(match result {
+            Ok(message) => format!("✅ **{}** {}", entry.name, message),
+            Err(message) => format!("❌ **{}** {}", entry.name, message),
+        });
+    }
+
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+
+    let uploaded = report.iter().filter(|r| r.starts_with('✅')).count();
+    let mut content = format!("Uploaded {} of {} sounds:\n", uploaded, report.len());
+
+    for (index, line) in report.iter().enumerate() {
+        if content.len() + line.len() + 30 > MESSAGE_CODE_LIMIT {
+            content.push_str(&format!("*...and {} more*", report.len() - index));
+            break;
+        }
+
+        content.push_str(line);
+        content.push('\n');
+    }
+
+    ctx.say(content).await?;
+
+    Ok(())
+}
+
+/// Replace the audio of a sound you have uploaded, keeping its ID
+#[poise::command(
+    slash_command,
+    rename = "replace",
+    category = "Manage",
+    default_member_permissions = "MANAGE_GUILD",
+    guild_only = true
+)]
+pub async fn replace_sound(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to replace"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+    #[description = "New sound file (max. 2MB)"] file: Attachment,
+    #[description = "Time in seconds to start the sound from"] start: Option<f64>,
+    #[description = "Time in seconds to end the sound at"] end: Option<f64>,
+    #[description = "Seconds to fade out over at the end"] fade_out: Option<f64>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    if let Some(sound) = owned_sound(ctx, &name, "replace").await? {
+        let trim = Trim {
+            start,
+            end,
+            fade_out,
+        };
+
+        match sound.replace(file.url.as_str(), &trim, ctx.data()).await {
+            Ok(upload) => {
+                ctx.say(format!(
+                    "Sound {} (ID {}) has been replaced with version {} ({}, {:.1}KB)",
+                    sound.name,
+                    upload.id,
+                    upload.version,
+                    upload_length(upload.duration),
+                    upload.size as f64 / 1024.0
+                ))
+                .await?;
+            }
+
+            Err(e) => {
+                ctx.say(upload_error_message(&e)).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Delete a sound you have uploaded
 #[poise::command(slash_command, rename = "delete", guild_only = true)]
 pub async fn delete_sound(
@@ -108,8 +390,6 @@ pub async fn delete_sound(
     #[autocomplete = "autocomplete_sound"]
     name: String,
 ) -> Result<(), Error> {
-    let pool = ctx.data().database.clone();
-
     let uid = ctx.author().id.0;
     let gid = ctx.guild_id().unwrap().0;
 
@@ -135,7 +415,7 @@ pub async fn delete_sound(
                 };
 
                 if sound.uploader_id == Some(uid) || has_perms {
-                    sound.delete(&pool).await?;
+                    sound.delete(ctx.data()).await?;
 
                     ctx.say("Sound has been deleted").await?;
                 } else {
@@ -196,6 +476,227 @@ pub async fn change_public(
     Ok(())
 }
 
+/// Make one of your sounds louder or quieter, on top of the server volume
+#[poise::command(slash_command, rename = "gain", guild_only = true)]
+pub async fn change_gain(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to change the gain of"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+    #[description = "Multiplier for the sound's volume (default: 1)"]
+    #[min = 0.1]
+    #[max = 4.0]
+    gain: f64,
+) -> Result<(), Error> {
+    let pool = ctx.data().database.clone();
+
+    let uid = ctx.author().id.0;
+    let gid = ctx.guild_id().unwrap().0;
+
+    let mut sound_vec = ctx.data().search_for_sound(&name, gid, uid, true).await?;
+
+    match sound_vec.first_mut() {
+        Some(sound) => {
+            if sound.uploader_id != Some(uid) {
+                ctx.say("You can only change the gain of sounds you have uploaded. Use `/list` to view your sounds").await?;
+            } else {
+                sound.gain = gain as f32;
+                sound.commit(&pool).await?;
+
+                ctx.say(format!("Gain of {} set to {:.2}x", sound.name, gain))
+                    .await?;
+            }
+        }
+
+        None => {
+            ctx.say("Sound could not be found by that name.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+const MAX_TAGS: usize = 10;
+const MAX_ALIASES: usize = 5;
+
+/// Find a sound the author uploaded, replying with the reason if there isn't one. Only the
+/// author's own uploads are considered, so a public or server sound with the same name can't get
+/// in the way
+async fn owned_sound(ctx: Context<'_>, name: &str, action: &str) -> Result<Option<Sound>, Error> {
+    let uid = ctx.author().id.0;
+
+    let sounds = ctx
+        .data()
+        .search_for_sound(name, ctx.guild_id().unwrap(), uid, true)
+        .await?;
+    let found_any = !sounds.is_empty();
+    let sound = sounds
+        .into_iter()
+        .find(|sound| sound.uploader_id == Some(uid));
+
+    match sound {
+        Some(sound) => Ok(Some(sound)),
+
+        None if found_any => {
+            ctx.say(format!(
+                "You can only {} sounds you have uploaded. Use `/list` to view your sounds",
+                action
+            ))
+            .await?;
+
+            Ok(None)
+        }
+
+        None => {
+            ctx.say("Sound could not be found by that name.").await?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Manage the tags of your sounds
+#[poise::command(
+    slash_command,
+    rename = "tag",
+    default_member_permissions = "MANAGE_GUILD",
+    guild_only = true
+)]
+pub async fn tag(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Tag one of your sounds, so it can be found with `tag:` in /search
+#[poise::command(slash_command, rename = "add", guild_only = true)]
+pub async fn add_tag(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to tag"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+    #[description = "Tag to add, e.g. \"meme\""] tag: String,
+) -> Result<(), Error> {
+    let tag = match normalise_tag(&tag) {
+        Some(tag) => tag,
+
+        None => {
+            ctx.say("Tags must be up to 20 letters, numbers, `-` or `_`")
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    if let Some(sound) = owned_sound(ctx, &name, "tag").await? {
+        let tags = sound.tags(&ctx.data().database).await?;
+
+        if tags.len() >= MAX_TAGS && !tags.contains(&tag) {
+            ctx.say(format!("Sounds can have at most {} tags", MAX_TAGS))
+                .await?;
+        } else {
+            sound.add_tag(&tag, &ctx.data().database).await?;
+
+            ctx.say(format!("Tagged {} with `{}`", sound.name, tag))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove a tag from one of your sounds
+#[poise::command(slash_command, rename = "remove", guild_only = true)]
+pub async fn remove_tag(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to untag"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+    #[description = "Tag to remove"] tag: String,
+) -> Result<(), Error> {
+    if let Some(sound) = owned_sound(ctx, &name, "untag").await? {
+        let tag = tag.trim().to_lowercase();
+
+        if sound.remove_tag(&tag, &ctx.data().database).await? {
+            ctx.say(format!("Removed tag `{}` from {}", tag, sound.name))
+                .await?;
+        } else {
+            ctx.say(format!("{} isn't tagged `{}`", sound.name, tag))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Manage the other names your sounds can be played by
+#[poise::command(
+    slash_command,
+    rename = "alias",
+    default_member_permissions = "MANAGE_GUILD",
+    guild_only = true
+)]
+pub async fn alias(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Let one of your sounds be played by another name
+#[poise::command(slash_command, rename = "add", guild_only = true)]
+pub async fn add_alias(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to add an alias to"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+    #[description = "Other name for the sound"] alias: String,
+) -> Result<(), Error> {
+    let alias = alias.trim();
+
+    if alias.is_empty() || alias.chars().count() > 20 || is_numeric(&alias.to_string()) {
+        ctx.say("Aliases must be up to 20 characters, and can't be just a number")
+            .await?;
+
+        return Ok(());
+    }
+
+    if let Some(sound) = owned_sound(ctx, &name, "add aliases to").await? {
+        let aliases = sound.aliases(&ctx.data().database).await?;
+
+        if aliases.len() >= MAX_ALIASES && !aliases.iter().any(|a| a == alias) {
+            ctx.say(format!("Sounds can have at most {} aliases", MAX_ALIASES))
+                .await?;
+        } else {
+            sound.add_alias(alias, &ctx.data().database).await?;
+
+            ctx.say(format!("{} can now be played as {}", sound.name, alias))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove an alias from one of your sounds
+#[poise::command(slash_command, rename = "remove", guild_only = true)]
+pub async fn remove_alias(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to remove an alias from"]
+    #[autocomplete = "autocomplete_sound"]
+    name: String,
+    #[description = "Alias to remove"] alias: String,
+) -> Result<(), Error> {
+    if let Some(sound) = owned_sound(ctx, &name, "remove aliases from").await? {
+        let alias = alias.trim();
+
+        if sound.remove_alias(alias, &ctx.data().database).await? {
+            ctx.say(format!("Removed alias {} from {}", alias, sound.name))
+                .await?;
+        } else {
+            ctx.say(format!("{} has no alias {}", sound.name, alias))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Download a sound file from the bot
 #[poise::command(slash_command, rename = "download", guild_only = true)]
 pub async fn download_file(
@@ -203,6 +704,7 @@ pub async fn download_file(
     #[description = "Name or ID of sound to download"]
     #[autocomplete = "autocomplete_sound"]
     name: String,
+    #[description = "File format to download as (default: opus)"] format: Option<DownloadFormat>,
 ) -> Result<(), Error> {
     ctx.defer().await?;
 
@@ -213,13 +715,31 @@ pub async fn download_file(
 
     match sound.first() {
         Some(sound) => {
-            let source = sound.store_sound_source(&ctx.data().database).await?;
+            let format = format.unwrap_or(DownloadFormat::Opus);
+            let source = sound.store_download_source(ctx.data(), &format).await?;
 
             let file = File::open(&source).await?;
-            let name = format!("{}-{}.opus", sound.id, sound.name);
-
-            ctx.send(|m| m.attachment((&file, name.as_str()).into()))
+            let size = file.metadata().await?.len();
+            let max_size = match ctx.guild().map(|g| g.premium_tier) {
+                Some(PremiumTier::Tier3) => 100 * 1024 * 1024,
+                Some(PremiumTier::Tier2) => 50 * 1024 * 1024,
+                _ => 25 * 1024 * 1024,
+            };
+
+            if size > max_size {
+                ctx.say(format!(
+                    "The {} file is too large to send ({:.1}MB, max. {}MB). Try a smaller format such as MP3 or Opus",
+                    format.extension(),
+                    size as f64 / 1024.0 / 1024.0,
+                    max_size / 1024 / 1024
+                ))
                 .await?;
+            } else {
+                let name = format!("{}-{}.{}", sound.id, sound.name, format.extension());
+
+                ctx.send(|m| m.attachment((&file, name.as_str()).into()))
+                    .await?;
+            }
         }
 
         None => {
@@ -1,8 +1,18 @@
-use crate::{models::sound::SoundCtx, Context};
+use crate::{
+    error::ErrorTypes,
+    models::{guild_data::CtxGuildData, sound::SoundCtx},
+    utils::rate_limit_retry,
+    Context, Error,
+};
 
+pub mod admin;
 pub mod info;
 pub mod manage;
+pub mod now_playing;
 pub mod play;
+pub mod playlist;
+pub mod queue;
+pub mod schedule;
 pub mod search;
 pub mod settings;
 pub mod stop;
@@ -22,3 +32,30 @@ pub async fn autocomplete_sound(
         })
         .collect()
 }
+
+/// Whether the author has Manage Server in the guild the command was used in
+pub async fn can_manage_guild(ctx: Context<'_>) -> bool {
+    match ctx.author_member().await {
+        Some(member) => member
+            .permissions(ctx.discord())
+            .map_or(false, |p| p.manage_guild()),
+
+        None => false,
+    }
+}
+
+/// Defer a command that plays sounds. A rate limited author gets their error before the response
+/// is deferred, so that only they see it
+pub async fn defer_play(ctx: Context<'_>) -> Result<(), Error> {
+    if let Some(member) = ctx.author_member().await {
+        let guild_data = ctx.guild_data(member.guild_id).await?.read().await.clone();
+
+        if let Some(retry_after) = rate_limit_retry(ctx.data(), &member, &guild_data).await? {
+            return Err(Box::new(ErrorTypes::RateLimited(retry_after)));
+        }
+    }
+
+    ctx.defer().await?;
+
+    Ok(())
+}
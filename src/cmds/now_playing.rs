@@ -0,0 +1,523 @@
+use std::{sync::Arc, time::Duration};
+
+use poise::serenity_prelude::{
+    self,
+    application::component::ButtonStyle,
+    async_trait,
+    interaction::{message_component::MessageComponentInteraction, InteractionResponseType},
+    ChannelId, CreateActionRow, CreateEmbed, GuildId, Http, MessageId,
+};
+use serde::{Deserialize, Serialize};
+use songbird::{
+    tracks::{LoopState, PlayMode, TrackHandle, TrackState},
+    Event, EventContext, EventHandler, Songbird, TrackEvent,
+};
+
+use crate::{
+    audio::{format_duration, Effects},
+    consts::THEME_COLOR,
+    error,
+    utils::{
+        end_track_loop, live_tracks, play_from_query, remember_loop, resume_track_loop,
+        segment_looping, Segment, TrackInfo,
+    },
+    Context, Data, Error,
+};
+
+/// How often the elapsed time on a panel is refreshed while a sound plays
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A message showing what is playing in a guild, kept up to date by track events
+#[derive(Clone)]
+pub struct NowPlayingPanel {
+    channel_id: ChannelId,
+    message_id: MessageId,
+    http: Arc<Http>,
+    songbird: Arc<Songbird>,
+    /// The last sound shown, so that it can be replayed after it has ended
+    last: Option<TrackInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+enum PanelAction {
+    Stop,
+    Skip,
+    Pause,
+    Loop,
+    Replay,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PanelButton {
+    panel: PanelAction,
+}
+
+/// The track a panel shows: the front of the queue, or else the newest sound playing
+async fn current_track(
+    data: &Data,
+    songbird: &Songbird,
+    guild_id: GuildId,
+) -> (Option<TrackHandle>, usize) {
+    let queue = match songbird.get(guild_id) {
+        Some(call) => Some(call.lock().await.queue().clone()),
+
+        None => None,
+    };
+    let queued = queue.as_ref().map_or(0, |q| q.len());
+
+    match queue.and_then(|q| q.current()) {
+        Some(handle) => (Some(handle), queued),
+
+        None => (
+            live_tracks(&data.active_tracks, guild_id).await.pop(),
+            queued,
+        ),
+    }
+}
+
+/// Whether a track is looping. Tracks repeating part of a sound seek back rather than use
+/// songbird's looping, so their state doesn't show it
+async fn is_looping(handle: &TrackHandle, state: &TrackState) -> bool {
+    match segment_looping(handle).await {
+        Some(looping) => looping,
+
+        None => match state.loops {
+            LoopState::Infinite => true,
+            LoopState::Finite(count) => count > 0,
+        },
+    }
+}
+
+impl NowPlayingPanel {
+    fn create_action_row(
+        &self,
+        state: Option<&TrackState>,
+        looping: bool,
+        can_replay: bool,
+    ) -> CreateActionRow {
+        let mut row = CreateActionRow::default();
+
+        let button_id = |action| serde_json::to_string(&PanelButton { panel: action }).unwrap();
+        let paused = state.map_or(false, |s| matches!(s.playing, PlayMode::Pause));
+
+        row.create_button(|b| {
+            b.custom_id(button_id(PanelAction::Stop))
+                .style(ButtonStyle::Danger)
+                .label("⏹️")
+                .disabled(state.is_none())
+        })
+        .create_button(|b| {
+            b.custom_id(button_id(PanelAction::Skip))
+                .style(ButtonStyle::Secondary)
+                .label("⏭️")
+                .disabled(state.is_none())
+        })
+        .create_button(|b| {
+            b.custom_id(button_id(PanelAction::Pause))
+                .style(if paused {
+                    ButtonStyle::Success
+                } else {
+                    ButtonStyle::Secondary
+                })
+                .label(if paused { "▶️" } else { "⏸️" })
+                .disabled(state.is_none())
+        })
+        .create_button(|b| {
+            b.custom_id(button_id(PanelAction::Loop))
+                .style(if looping {
+                    ButtonStyle::Success
+                } else {
+                    ButtonStyle::Secondary
+                })
+                .label("🔁")
+                .disabled(state.is_none())
+        })
+        .create_button(|b| {
+            b.custom_id(button_id(PanelAction::Replay))
+                .style(ButtonStyle::Primary)
+                .label("🔄")
+                .disabled(!can_replay)
+        });
+
+        row
+    }
+
+    fn embed(
+        &self,
+        info: Option<&TrackInfo>,
+        state: Option<&TrackState>,
+        looping: bool,
+        queued: usize,
+    ) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+
+        embed
+            .color(THEME_COLOR)
+            .title("Now playing")
+            .footer(|f| f.text(format!("{} sounds in queue", queued)));
+
+        match (info, state) {
+            (Some(info), Some(state)) => {
+                let elapsed = format_duration(state.position.as_secs_f64());
+                let progress = match info.duration {
+                    Some(duration) => format!("{} / {}", elapsed, format_duration(duration)),
+
+                    None => elapsed,
+                };
+                let status = if matches!(state.playing, PlayMode::Pause) {
+                    "Paused"
+                } else if looping {
+                    "Looping"
+                } else {
+                    "Playing"
+                };
+
+                embed.description(format!(
+                    "**{}** (ID `{}`)\n{} · {}\nRequested by <@{}>",
+                    info.name, info.sound_id, status, progress, info.user_id
+                ));
+            }
+
+            (None, Some(state)) => {
+                embed.description(format!(
+                    "Unknown sound\n{}",
+                    format_duration(state.position.as_secs_f64())
+                ));
+            }
+
+            _ => {
+                embed.description("Nothing is playing");
+            }
+        }
+
+        embed
+    }
+}
+
+/// Refresh the now playing panel of a guild, if it has one
+pub async fn update_panel(data: &Data, guild_id: GuildId) {
+    let panel = match data.now_playing.get(&guild_id) {
+        Some(panel) => panel.clone(),
+
+        None => return,
+    };
+
+    let (current, queued) = current_track(data, &panel.songbird, guild_id).await;
+    let (state, info) = match &current {
+        Some(handle) => (handle.get_info().await.ok(), TrackInfo::of(handle).await),
+
+        None => (None, None),
+    };
+    // a track that has finished can't report its state
+    let info = info.filter(|_| state.is_some());
+    let looping = match (&current, &state) {
+        (Some(handle), Some(state)) => is_looping(handle, state).await,
+
+        _ => false,
+    };
+
+    if info.is_some() {
+        if let Some(mut panel) = data.now_playing.get_mut(&guild_id) {
+            panel.last = info.clone();
+        }
+    }
+
+    let embed = panel.embed(info.as_ref(), state.as_ref(), looping, queued);
+    let row = panel.create_action_row(
+        state.as_ref(),
+        looping,
+        info.is_some() || panel.last.is_some(),
+    );
+
+    let edit = panel
+        .channel_id
+        .edit_message(&panel.http, panel.message_id, |m| {
+            m.set_embed(embed).components(|c| c.add_action_row(row))
+        })
+        .await;
+
+    // the message has been deleted or can't be edited any more
+    if edit.is_err() {
+        data.now_playing.remove(&guild_id);
+    }
+}
+
+/// Remove the guild's panel once the bot has left voice, leaving its message showing that nothing
+/// is playing with every button disabled
+pub async fn close_panel(data: &Data, guild_id: GuildId) {
+    let panel = match data.now_playing.remove(&guild_id) {
+        Some((_, panel)) => panel,
+
+        None => return,
+    };
+
+    let embed = panel.embed(None, None, false, 0);
+    let row = panel.create_action_row(None, false, false);
+
+    let _ = panel
+        .channel_id
+        .edit_message(&panel.http, panel.message_id, |m| {
+            m.set_embed(embed).components(|c| c.add_action_row(row))
+        })
+        .await;
+}
+
+/// Show a now playing panel on a message, replacing any existing panel in the guild
+pub async fn create_panel(
+    ctx: Context<'_>,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    ctx.data().now_playing.insert(
+        guild_id,
+        NowPlayingPanel {
+            channel_id,
+            message_id,
+            http: ctx.discord().http.clone(),
+            songbird: songbird::get(ctx.discord()).await.unwrap(),
+            last: None,
+        },
+    );
+
+    update_panel(ctx.data(), guild_id).await;
+
+    tokio::spawn(refresh_panel(ctx.data().clone(), guild_id, message_id));
+
+    Ok(())
+}
+
+/// Refresh a panel's elapsed time until it is replaced or removed. Runs once per panel, however
+/// many tracks are playing
+async fn refresh_panel(data: Data, guild_id: GuildId, message_id: MessageId) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    // the first tick completes immediately, and the panel has only just been drawn
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let songbird = match data.now_playing.get(&guild_id) {
+            Some(panel) if panel.message_id == message_id => panel.songbird.clone(),
+
+            _ => return,
+        };
+
+        if current_track(&data, &songbird, guild_id).await.0.is_some() {
+            update_panel(&data, guild_id).await;
+        }
+    }
+}
+
+/// Keeps a guild's now playing panel in step with a track
+struct PanelUpdater {
+    data: Data,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl EventHandler for PanelUpdater {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        update_panel(&self.data, self.guild_id).await;
+
+        None
+    }
+}
+
+/// Update the guild's now playing panel when a track starts and ends
+pub fn attach_panel_events(handle: &TrackHandle, data: &Data, guild_id: GuildId) {
+    let events = vec![
+        Event::Track(TrackEvent::Play),
+        Event::Track(TrackEvent::End),
+    ];
+
+    for event in events {
+        let _ = handle.add_event(
+            event,
+            PanelUpdater {
+                data: data.clone(),
+                guild_id,
+            },
+        );
+    }
+}
+
+impl PanelButton {
+    pub async fn handle_interaction(
+        ctx: &serenity_prelude::Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+    ) -> Result<(), Error> {
+        let guild_id = interaction.guild_id.unwrap();
+        let button = serde_json::from_str::<Self>(&interaction.data.custom_id)?;
+
+        let panel = match data.now_playing.get(&guild_id) {
+            Some(panel) if panel.message_id == interaction.message.id => panel.clone(),
+
+            _ => {
+                interaction
+                    .create_interaction_response(ctx, |r| {
+                        r.kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|d| {
+                                d.ephemeral(true)
+                                    .content("This panel is no longer active. Use `/play` with `panel` to show a new one")
+                            })
+                    })
+                    .await?;
+
+                return Ok(());
+            }
+        };
+
+        if !can_control(ctx, &panel.songbird, guild_id, interaction).await {
+            interaction
+                .create_interaction_response(ctx, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|d| {
+                            d.ephemeral(true).content(
+                                "You need to be in the bot's voice channel to use this panel",
+                            )
+                        })
+                })
+                .await?;
+
+            return Ok(());
+        }
+
+        interaction
+            .create_interaction_response(ctx, |r| {
+                r.kind(InteractionResponseType::DeferredUpdateMessage)
+            })
+            .await?;
+
+        let (current, _) = current_track(data, &panel.songbird, guild_id).await;
+        let state = match &current {
+            Some(handle) => handle.get_info().await.ok(),
+
+            None => None,
+        };
+
+        match (button.panel, current, state) {
+            (PanelAction::Stop, _, _) => {
+                if let Some(call) = panel.songbird.get(guild_id) {
+                    call.lock().await.stop();
+                }
+
+                data.active_tracks.remove(&guild_id);
+                data.looping_tracks.remove(&guild_id);
+            }
+
+            (PanelAction::Skip, Some(handle), Some(_)) => {
+                // stopping the front of the queue starts the next sound
+                let _ = handle.stop();
+            }
+
+            (PanelAction::Pause, Some(handle), Some(state)) => {
+                let _ = if matches!(state.playing, PlayMode::Pause) {
+                    handle.play()
+                } else {
+                    handle.pause()
+                };
+            }
+
+            (PanelAction::Loop, Some(handle), Some(state)) => {
+                if is_looping(&handle, &state).await {
+                    end_track_loop(&handle).await;
+                } else if resume_track_loop(&handle).await.is_ok() {
+                    remember_loop(&handle, data, guild_id);
+                }
+            }
+
+            (PanelAction::Replay, Some(handle), Some(_)) => {
+                let _ = handle.seek_time(Duration::default());
+            }
+
+            (PanelAction::Replay, _, _) => {
+                if let Some(last) = panel.last {
+                    let content = match replay(ctx, data, guild_id, interaction, &last).await {
+                        Ok(content) => content,
+
+                        Err(e) => error::user_message(&e),
+                    };
+
+                    interaction
+                        .create_followup_message(ctx, |m| m.ephemeral(true).content(content))
+                        .await?;
+                }
+            }
+
+            _ => {}
+        }
+
+        update_panel(data, guild_id).await;
+
+        Ok(())
+    }
+}
+
+/// Whether a user may press a panel's buttons: they must be able to speak and be in the bot's
+/// voice channel, unless they manage the server
+async fn can_control(
+    ctx: &serenity_prelude::Context,
+    songbird: &Songbird,
+    guild_id: GuildId,
+    interaction: &MessageComponentInteraction,
+) -> bool {
+    let permissions = interaction.member.as_ref().and_then(|m| m.permissions);
+
+    if permissions.map_or(false, |p| p.manage_guild()) {
+        return true;
+    }
+
+    let user_channel = guild_id.to_guild_cached(ctx).and_then(|guild| {
+        guild
+            .voice_states
+            .get(&interaction.user.id)
+            .and_then(|voice_state| voice_state.channel_id)
+    });
+    let bot_channel = match songbird.get(guild_id) {
+        Some(call) => call.lock().await.current_channel(),
+
+        None => None,
+    };
+
+    let in_call = match (user_channel, bot_channel) {
+        (Some(user_channel), Some(bot_channel)) => bot_channel == user_channel.into(),
+
+        // with the bot out of voice only Replay does anything, and it plays in the user's channel
+        (Some(_), None) => true,
+
+        (None, _) => false,
+    };
+
+    in_call && permissions.map_or(false, |p| p.speak())
+}
+
+/// Play a sound that has finished again, with the same checks as `/play`
+async fn replay(
+    ctx: &serenity_prelude::Context,
+    data: &Data,
+    guild_id: GuildId,
+    interaction: &MessageComponentInteraction,
+    last: &TrackInfo,
+) -> Result<String, Error> {
+    let (guild, member) = match (guild_id.to_guild_cached(ctx), &interaction.member) {
+        (Some(guild), Some(member)) => (guild, member),
+
+        _ => return Ok("This server couldn't be found".to_string()),
+    };
+
+    play_from_query(
+        ctx,
+        data,
+        guild,
+        member,
+        None,
+        &last.sound_id.to_string(),
+        None,
+        Segment::default(),
+        &Effects::default(),
+    )
+    .await
+}
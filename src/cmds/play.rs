@@ -1,55 +1,132 @@
+use std::time::Duration;
+
+use log::warn;
 use poise::serenity_prelude::{
     builder::CreateActionRow, model::application::component::ButtonStyle, GuildChannel,
 };
 
 use crate::{
-    cmds::autocomplete_sound,
+    audio::Effects,
+    cmds::{autocomplete_sound, defer_play, now_playing::create_panel},
     models::{guild_data::CtxGuildData, sound::SoundCtx},
-    utils::{join_channel, play_from_query, queue_audio},
+    utils::{check_rate_limit, join_channel, play_from_query, queue_audio, Loop, Segment},
     Context, Error,
 };
 
+/// Parse the effects option of a play command, replying with the reason if it is invalid
+async fn parse_effects(
+    ctx: Context<'_>,
+    effects: Option<String>,
+) -> Result<Option<Effects>, Error> {
+    match effects.as_deref().map(|e| e.parse::<Effects>()).transpose() {
+        Ok(effects) => Ok(Some(effects.unwrap_or_default())),
+
+        Err(reason) => {
+            ctx.say(reason).await?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Parse the start and duration options of a play command, replying with the reason if they are
+/// invalid
+async fn parse_segment(
+    ctx: Context<'_>,
+    start: Option<f64>,
+    duration: Option<f64>,
+) -> Result<Option<Segment>, Error> {
+    if start.map_or(false, |s| !s.is_finite() || s < 0.0) {
+        ctx.say("The start time cannot be negative.").await?;
+
+        return Ok(None);
+    }
+    if duration.map_or(false, |d| !d.is_finite() || d <= 0.0) {
+        ctx.say("The duration must be longer than 0 seconds.")
+            .await?;
+
+        return Ok(None);
+    }
+
+    Ok(Some(Segment {
+        start: start.map_or_else(Duration::default, Duration::from_secs_f64),
+        duration: duration.map(Duration::from_secs_f64),
+    }))
+}
+
 /// Play a sound in your current voice channel
 #[poise::command(slash_command, default_member_permissions = "SPEAK", guild_only = true)]
 pub async fn play(
     ctx: Context<'_>,
-    #[description = "Name or ID of sound to play"]
+    #[description = "Name or ID of sound to play. Use \"a > b\" to play in order, or \"a + b\" together"]
     #[autocomplete = "autocomplete_sound"]
     name: String,
     #[description = "Channel to play in (default: your current voice channel)"]
     #[channel_types("Voice")]
     channel: Option<GuildChannel>,
+    #[description = "Effects to apply, e.g. \"speed=1.5 pitch=-2 reverse echo lowpass=800 bass=6\""]
+    effects: Option<String>,
+    #[description = "Time in seconds to start the sound from"]
+    #[min = 0]
+    start: Option<f64>,
+    #[description = "Seconds of the sound to play (default: until it ends)"]
+    #[min = 0]
+    duration: Option<f64>,
+    #[description = "Show a panel with the sound playing and playback controls"] panel: Option<
+        bool,
+    >,
 ) -> Result<(), Error> {
-    ctx.defer().await?;
+    defer_play(ctx).await?;
+
+    let effects = match parse_effects(ctx, effects).await? {
+        Some(effects) => effects,
+
+        None => return Ok(()),
+    };
+    let segment = match parse_segment(ctx, start, duration).await? {
+        Some(segment) => segment,
+
+        None => return Ok(()),
+    };
 
     let guild = ctx.guild().unwrap();
+    let member = ctx.author_member().await.unwrap();
 
     if channel.as_ref().map_or(false, |c| c.is_text_based()) {
         ctx.say("The channel specified is not a voice channel.")
             .await?;
     } else {
-        ctx.say(
-            play_from_query(
-                &ctx.discord(),
-                &ctx.data(),
-                guild,
-                ctx.author().id,
-                channel.map(|c| c.id),
-                &name,
-                false,
+        let reply = ctx
+            .say(
+                play_from_query(
+                    &ctx.discord(),
+                    &ctx.data(),
+                    guild,
+                    &member,
+                    channel.map(|c| c.id),
+                    &name,
+                    None,
+                    segment,
+                    &effects,
+                )
+                .await?,
             )
-            .await,
-        )
-        .await?;
+            .await?;
+
+        if panel.unwrap_or(false) {
+            let message = reply.message().await?;
+
+            create_panel(ctx, message.channel_id, message.id).await?;
+        }
     }
 
     Ok(())
 }
 
-/// Play up to 25 sounds on queue
+/// Add up to 24 sounds to the queue
 #[poise::command(
     slash_command,
-    rename = "queue",
+    rename = "add",
     default_member_permissions = "SPEAK",
     guild_only = true
 )]
@@ -127,11 +204,16 @@ pub async fn queue_play(
     #[description = "Name or ID for queue position 24"]
     #[autocomplete = "autocomplete_sound"]
     sound_24: Option<String>,
-    #[description = "Name or ID for queue position 25"]
-    #[autocomplete = "autocomplete_sound"]
-    sound_25: Option<String>,
+    // Discord allows 25 options per command, so this takes the place of a 25th sound
+    #[description = "Effects to apply to every sound"] effects: Option<String>,
 ) -> Result<(), Error> {
-    ctx.defer().await?;
+    defer_play(ctx).await?;
+
+    let effects = match parse_effects(ctx, effects).await? {
+        Some(effects) => effects,
+
+        None => return Ok(()),
+    };
 
     let guild = ctx.guild().unwrap();
 
@@ -142,13 +224,15 @@ pub async fn queue_play(
 
     match channel_to_join {
         Some(user_channel) => {
-            let (call_handler, _) = join_channel(ctx.discord(), guild.clone(), user_channel).await;
+            let guild_data = ctx.data().guild_data(ctx.guild_id().unwrap()).await?;
+            let guild_data = guild_data.read().await.clone();
 
-            let guild_data = ctx
-                .data()
-                .guild_data(ctx.guild_id().unwrap())
-                .await
-                .unwrap();
+            // a whole queue counts as one play
+            let member = ctx.author_member().await.unwrap();
+            check_rate_limit(ctx.data(), &member, &guild_data).await?;
+
+            let (call_handler, _) =
+                join_channel(ctx.discord(), ctx.data(), guild.clone(), user_channel).await;
 
             let mut lock = call_handler.lock().await;
 
@@ -177,10 +261,12 @@ pub async fn queue_play(
                 sound_22,
                 sound_23,
                 sound_24,
-                sound_25,
             ];
 
+            let max_duration = guild_data.max_play_duration;
+
             let mut sounds = vec![];
+            let mut too_long = 0;
 
             for sound in query_terms.iter().flatten() {
                 let search = ctx
@@ -189,20 +275,45 @@ pub async fn queue_play(
                     .await?;
 
                 if let Some(sound) = search.first() {
-                    sounds.push(sound.clone());
+                    if sound.too_long(max_duration) {
+                        too_long += 1;
+                    } else {
+                        sounds.push(sound.clone());
+                    }
                 }
             }
 
-            queue_audio(
+            let failed = queue_audio(
                 &sounds,
-                guild_data.read().await.volume,
+                ctx.author().id,
+                &guild_data,
                 &mut lock,
-                &ctx.data().database,
+                ctx.data(),
+                &effects,
             )
-            .await
-            .unwrap();
+            .await;
+
+            for (sound, e) in &failed {
+                warn!(
+                    "Failed to queue sound {} in guild {}: {}",
+                    sound.id, guild.id, e
+                );
+            }
 
-            ctx.say(format!("Queued {} sounds!", sounds.len())).await?;
+            let mut content = format!("Queued {} sounds!", sounds.len() - failed.len());
+
+            if too_long > 0 {
+                content.push_str(&format!(
+                    " {} sounds were skipped for being longer than {}s.",
+                    too_long,
+                    max_duration.unwrap_or(0)
+                ));
+            }
+            if !failed.is_empty() {
+                content.push_str(&format!(" {} sounds couldn't be loaded.", failed.len()));
+            }
+
+            ctx.say(content).await?;
         }
         None => {
             ctx.say("You are not in a voice chat!").await?;
@@ -224,22 +335,53 @@ pub async fn loop_play(
     #[description = "Name or ID of sound to loop"]
     #[autocomplete = "autocomplete_sound"]
     name: String,
+    #[description = "Number of times to repeat the sound (default: until stopped)"]
+    #[min = 1]
+    repeats: Option<usize>,
+    #[description = "Stop looping after this many seconds (default: until stopped)"]
+    #[min = 1]
+    max_duration: Option<u64>,
+    #[description = "Effects to apply, e.g. \"speed=1.5 pitch=-2 reverse echo lowpass=800 bass=6\""]
+    effects: Option<String>,
+    #[description = "Time in seconds to start each repeat from"]
+    #[min = 0]
+    start: Option<f64>,
+    #[description = "Seconds of the sound to play each repeat (default: until it ends)"]
+    #[min = 0]
+    duration: Option<f64>,
 ) -> Result<(), Error> {
-    ctx.defer().await?;
+    defer_play(ctx).await?;
+
+    let effects = match parse_effects(ctx, effects).await? {
+        Some(effects) => effects,
+
+        None => return Ok(()),
+    };
+    let segment = match parse_segment(ctx, start, duration).await? {
+        Some(segment) => segment,
+
+        None => return Ok(()),
+    };
 
     let guild = ctx.guild().unwrap();
+    let member = ctx.author_member().await.unwrap();
 
     ctx.say(
         play_from_query(
             &ctx.discord(),
             &ctx.data(),
             guild,
-            ctx.author().id,
+            &member,
             None,
             &name,
-            true,
+            Some(Loop {
+                count: repeats,
+                max_duration: max_duration.map(Duration::from_secs),
+            }),
+            segment,
+            &effects,
         )
-        .await,
+        .await?,
     )
     .await?;
 
@@ -0,0 +1,515 @@
+use log::warn;
+use poise::serenity_prelude::GuildChannel;
+
+use crate::{
+    audio::Effects,
+    cmds::{autocomplete_sound, can_manage_guild, defer_play},
+    consts::THEME_COLOR,
+    models::{
+        guild_data::CtxGuildData,
+        playlist::{Playlist, PlaylistOwner},
+        sound::SoundCtx,
+    },
+    utils::{check_rate_limit, join_channel, queue_audio},
+    Context, Error,
+};
+
+const MAX_PLAYLISTS: u64 = 25;
+const MAX_PLAYLIST_SOUNDS: usize = 50;
+const NOT_FOUND: &str = "No playlist found by that name. Use `/playlist list` to view playlists";
+
+pub async fn autocomplete_playlist(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<poise::AutocompleteChoice<String>> {
+    Playlist::available(
+        ctx.guild_id().unwrap(),
+        ctx.author().id,
+        &ctx.data().database,
+    )
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .filter(|p| p.name.to_lowercase().contains(&partial.to_lowercase()))
+    .take(25)
+    .map(|p| poise::AutocompleteChoice {
+        name: p.name.clone(),
+        value: p.name,
+    })
+    .collect()
+}
+
+/// Find a playlist the author can change, replying with the reason if there isn't one
+async fn editable_playlist(ctx: Context<'_>, name: &str) -> Result<Option<Playlist>, Error> {
+    let playlist = Playlist::find(
+        name,
+        ctx.guild_id().unwrap(),
+        ctx.author().id,
+        &ctx.data().database,
+    )
+    .await?;
+    let manage_guild = can_manage_guild(ctx).await;
+
+    match playlist {
+        Some(playlist) if playlist.editable_by(ctx.author().id, manage_guild) => Ok(Some(playlist)),
+
+        Some(_) => {
+            ctx.say("You need the Manage Server permission to change server playlists")
+                .await?;
+
+            Ok(None)
+        }
+
+        None => {
+            ctx.say(NOT_FOUND).await?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Manage saved playlists of sounds
+#[poise::command(
+    slash_command,
+    rename = "playlist",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn playlist(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Create a new playlist
+#[poise::command(slash_command, rename = "create", guild_only = true)]
+pub async fn create_playlist(
+    ctx: Context<'_>,
+    #[description = "Name of the playlist"] name: String,
+    #[description = "Who the playlist belongs to (default: me)"] owner: Option<PlaylistOwner>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let owner = owner.unwrap_or(PlaylistOwner::User);
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > 32 {
+        ctx.say("Playlist names must be between 1 and 32 characters")
+            .await?;
+
+        return Ok(());
+    }
+
+    if owner == PlaylistOwner::Guild && !can_manage_guild(ctx).await {
+        ctx.say("You need the Manage Server permission to create server playlists")
+            .await?;
+
+        return Ok(());
+    }
+
+    let existing = Playlist::available(guild_id, ctx.author().id, &ctx.data().database)
+        .await?
+        .into_iter()
+        .any(|p| {
+            p.name.to_lowercase() == name.to_lowercase()
+                && match owner {
+                    PlaylistOwner::User => p.user_id.is_some(),
+                    PlaylistOwner::Guild => p.user_id.is_none(),
+                }
+        });
+
+    if existing {
+        ctx.say("A playlist already exists with that name").await?;
+
+        return Ok(());
+    }
+
+    if Playlist::count_owned(owner, guild_id, ctx.author().id, &ctx.data().database).await?
+        >= MAX_PLAYLISTS
+    {
+        ctx.say(format!(
+            "You can have at most {} playlists. Delete one with `/playlist delete` first",
+            MAX_PLAYLISTS
+        ))
+        .await?;
+
+        return Ok(());
+    }
+
+    // checked again by the database, in case the same playlist was created at the same time
+    match Playlist::create(name, owner, guild_id, ctx.author().id, &ctx.data().database).await? {
+        Some(_) => {
+            ctx.say(format!(
+                "Playlist {} created. Add sounds with `/playlist add`",
+                name
+            ))
+            .await?;
+        }
+
+        None => {
+            ctx.say("A playlist already exists with that name").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Add a sound to the end of a playlist
+#[poise::command(slash_command, rename = "add", guild_only = true)]
+pub async fn add_to_playlist(
+    ctx: Context<'_>,
+    #[description = "Name of the playlist"]
+    #[autocomplete = "autocomplete_playlist"]
+    playlist: String,
+    #[description = "Name or ID of sound to add"]
+    #[autocomplete = "autocomplete_sound"]
+    sound: String,
+) -> Result<(), Error> {
+    let playlist = match editable_playlist(ctx, &playlist).await? {
+        Some(playlist) => playlist,
+
+        None => return Ok(()),
+    };
+
+    let search = ctx
+        .data()
+        .search_for_sound(&sound, ctx.guild_id().unwrap(), ctx.author().id, true)
+        .await?;
+
+    let sound = match search.into_iter().next() {
+        Some(sound) => sound,
+
+        None => {
+            ctx.say("Sound could not be found by that name.").await?;
+
+            return Ok(());
+        }
+    };
+    let name = sound.name.clone();
+
+    let added = playlist
+        .edit_sounds(&ctx.data().database, |sounds| {
+            if sounds.len() >= MAX_PLAYLIST_SOUNDS {
+                Err(format!(
+                    "Playlists can have at most {} sounds",
+                    MAX_PLAYLIST_SOUNDS
+                ))
+            } else {
+                sounds.push(sound);
+
+                Ok(sounds.len())
+            }
+        })
+        .await?;
+
+    match added {
+        Ok(position) => {
+            ctx.say(format!(
+                "Added {} to {} at position {}",
+                name, playlist.name, position
+            ))
+            .await?;
+        }
+
+        Err(reason) => {
+            ctx.say(reason).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove a sound from a playlist
+#[poise::command(slash_command, rename = "remove", guild_only = true)]
+pub async fn remove_from_playlist(
+    ctx: Context<'_>,
+    #[description = "Name of the playlist"]
+    #[autocomplete = "autocomplete_playlist"]
+    playlist: String,
+    #[description = "Position of the sound, from /playlist list"]
+    #[min = 1]
+    position: usize,
+) -> Result<(), Error> {
+    let playlist = match editable_playlist(ctx, &playlist).await? {
+        Some(playlist) => playlist,
+
+        None => return Ok(()),
+    };
+
+    let removed = playlist
+        .edit_sounds(&ctx.data().database, |sounds| {
+            if position > sounds.len() {
+                Err(format!(
+                    "{} only has {} sounds",
+                    playlist.name,
+                    sounds.len()
+                ))
+            } else {
+                Ok(sounds.remove(position - 1))
+            }
+        })
+        .await?;
+
+    match removed {
+        Ok(removed) => {
+            ctx.say(format!("Removed {} from {}", removed.name, playlist.name))
+                .await?;
+        }
+
+        Err(reason) => {
+            ctx.say(reason).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move a sound to a different position in a playlist
+#[poise::command(slash_command, rename = "move", guild_only = true)]
+pub async fn move_in_playlist(
+    ctx: Context<'_>,
+    #[description = "Name of the playlist"]
+    #[autocomplete = "autocomplete_playlist"]
+    playlist: String,
+    #[description = "Current position of the sound"]
+    #[min = 1]
+    from: usize,
+    #[description = "New position of the sound"]
+    #[min = 1]
+    to: usize,
+) -> Result<(), Error> {
+    let playlist = match editable_playlist(ctx, &playlist).await? {
+        Some(playlist) => playlist,
+
+        None => return Ok(()),
+    };
+
+    let moved = playlist
+        .edit_sounds(&ctx.data().database, |sounds| {
+            if from > sounds.len() || to > sounds.len() {
+                Err(format!(
+                    "{} only has {} sounds",
+                    playlist.name,
+                    sounds.len()
+                ))
+            } else {
+                let sound = sounds.remove(from - 1);
+                let name = sound.name.clone();
+                sounds.insert(to - 1, sound);
+
+                Ok(name)
+            }
+        })
+        .await?;
+
+    match moved {
+        Ok(name) => {
+            ctx.say(format!("Moved {} to position {}", name, to))
+                .await?;
+        }
+
+        Err(reason) => {
+            ctx.say(reason).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete a playlist. The sounds in it are kept
+#[poise::command(slash_command, rename = "delete", guild_only = true)]
+pub async fn delete_playlist(
+    ctx: Context<'_>,
+    #[description = "Name of the playlist"]
+    #[autocomplete = "autocomplete_playlist"]
+    playlist: String,
+) -> Result<(), Error> {
+    if let Some(playlist) = editable_playlist(ctx, &playlist).await? {
+        playlist.delete(&ctx.data().database).await?;
+
+        ctx.say(format!("Playlist {} deleted", playlist.name))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// View your playlists and this server's, or the sounds in one playlist
+#[poise::command(slash_command, rename = "list", guild_only = true)]
+pub async fn list_playlists(
+    ctx: Context<'_>,
+    #[description = "Name of a playlist to view the sounds of"]
+    #[autocomplete = "autocomplete_playlist"]
+    playlist: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    let (title, lines) = match playlist {
+        Some(name) => {
+            match Playlist::find(&name, guild_id, ctx.author().id, &ctx.data().database).await? {
+                Some(playlist) => {
+                    let lines = playlist
+                        .sounds(&ctx.data().database)
+                        .await?
+                        .iter()
+                        .enumerate()
+                        .map(|(position, sound)| {
+                            format!("`{}` {} (ID {})", position + 1, sound.name, sound.id)
+                        })
+                        .collect::<Vec<_>>();
+
+                    (playlist.name, lines)
+                }
+
+                None => {
+                    ctx.say(NOT_FOUND).await?;
+
+                    return Ok(());
+                }
+            }
+        }
+
+        None => {
+            let lines = Playlist::available(guild_id, ctx.author().id, &ctx.data().database)
+                .await?
+                .iter()
+                .map(|playlist| {
+                    format!(
+                        "{} ({})",
+                        playlist.name,
+                        if playlist.user_id.is_some() {
+                            "yours"
+                        } else {
+                            "server"
+                        }
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            ("Playlists".to_string(), lines)
+        }
+    };
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title(title)
+                .color(THEME_COLOR)
+                .description(if lines.is_empty() {
+                    "Nothing here yet".to_string()
+                } else {
+                    lines.join("\n")
+                })
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Queue the sounds in a playlist
+#[poise::command(
+    slash_command,
+    rename = "play",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn play_playlist(
+    ctx: Context<'_>,
+    #[description = "Name of the playlist"]
+    #[autocomplete = "autocomplete_playlist"]
+    playlist: String,
+    #[description = "Channel to play in (default: your current voice channel)"]
+    #[channel_types("Voice")]
+    channel: Option<GuildChannel>,
+) -> Result<(), Error> {
+    defer_play(ctx).await?;
+
+    let guild = ctx.guild().unwrap();
+
+    let playlist =
+        match Playlist::find(&playlist, guild.id, ctx.author().id, &ctx.data().database).await? {
+            Some(playlist) => playlist,
+
+            None => {
+                ctx.say(NOT_FOUND).await?;
+
+                return Ok(());
+            }
+        };
+
+    let channel_to_join = channel.map(|c| c.id).or_else(|| {
+        guild
+            .voice_states
+            .get(&ctx.author().id)
+            .and_then(|voice_state| voice_state.channel_id)
+    });
+
+    let user_channel = match channel_to_join {
+        Some(channel) => channel,
+
+        None => {
+            ctx.say("You are not in a voice chat!").await?;
+
+            return Ok(());
+        }
+    };
+
+    let guild_data = ctx.guild_data(guild.id).await?.read().await.clone();
+    let max_duration = guild_data.max_play_duration;
+
+    let (sounds, too_long): (Vec<_>, Vec<_>) = playlist
+        .playable_sounds(guild.id, ctx.author().id, &ctx.data().database)
+        .await?
+        .into_iter()
+        .partition(|sound| !sound.too_long(max_duration));
+
+    if sounds.is_empty() {
+        ctx.say(format!("{} has no sounds you can play", playlist.name))
+            .await?;
+
+        return Ok(());
+    }
+
+    // a whole playlist counts as one play, like `/queue add`
+    let member = ctx.author_member().await.unwrap();
+    check_rate_limit(ctx.data(), &member, &guild_data).await?;
+
+    let (call_handler, _) =
+        join_channel(ctx.discord(), ctx.data(), guild.clone(), user_channel).await;
+    let mut lock = call_handler.lock().await;
+
+    let failed = queue_audio(
+        &sounds,
+        ctx.author().id,
+        &guild_data,
+        &mut lock,
+        ctx.data(),
+        &Effects::default(),
+    )
+    .await;
+
+    for (sound, e) in &failed {
+        warn!(
+            "Failed to queue sound {} in guild {}: {}",
+            sound.id, guild.id, e
+        );
+    }
+
+    let mut content = format!(
+        "Queued {} sounds from {}!",
+        sounds.len() - failed.len(),
+        playlist.name
+    );
+
+    if !too_long.is_empty() {
+        content.push_str(&format!(
+            " {} sounds were skipped for being longer than {}s.",
+            too_long.len(),
+            max_duration.unwrap_or(0)
+        ));
+    }
+    if !failed.is_empty() {
+        content.push_str(&format!(" {} sounds couldn't be loaded.", failed.len()));
+    }
+
+    ctx.say(content).await?;
+
+    Ok(())
+}
@@ -0,0 +1,492 @@
+use poise::serenity_prelude::{
+    self,
+    application::component::ButtonStyle,
+    interaction::{message_component::MessageComponentInteraction, InteractionResponseType},
+    CreateActionRow, CreateEmbed, GuildId,
+};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use songbird::tracks::TrackQueue;
+
+use crate::{audio::format_duration, consts::THEME_COLOR, utils::TrackInfo, Context, Error};
+
+const PAGE_SIZE: usize = 10;
+const NOTHING_QUEUED: &str = "There is nothing in the queue. Add sounds with `/queue add`";
+
+async fn guild_queue(ctx: &serenity_prelude::Context, guild_id: GuildId) -> Option<TrackQueue> {
+    let call = songbird::get(ctx).await.unwrap().get(guild_id)?;
+    let queue = call.lock().await.queue().clone();
+
+    if queue.is_empty() {
+        None
+    } else {
+        Some(queue)
+    }
+}
+
+/// Manage the play queue
+#[poise::command(slash_command, rename = "queue", guild_only = true)]
+pub async fn queue(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+struct QueueEntry {
+    info: Option<TrackInfo>,
+    /// How far through the sound playback is. Only known for the sound currently playing
+    elapsed: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueuePager {
+    nonce: u64,
+    queue_page: usize,
+}
+
+impl QueuePager {
+    async fn entries(queue: &TrackQueue) -> Vec<QueueEntry> {
+        let mut entries = vec![];
+
+        for (index, handle) in queue.current_queue().iter().enumerate() {
+            let elapsed = if index == 0 {
+                handle
+                    .get_info()
+                    .await
+                    .ok()
+                    .map(|state| state.position.as_secs_f64())
+            } else {
+                None
+            };
+
+            entries.push(QueueEntry {
+                info: TrackInfo::of(handle).await,
+                elapsed,
+            });
+        }
+
+        entries
+    }
+
+    fn max_page(count: usize) -> usize {
+        count.saturating_sub(1) / PAGE_SIZE
+    }
+
+    fn create_action_row(&self, max_page: usize) -> CreateActionRow {
+        let mut row = CreateActionRow::default();
+
+        row.create_button(|b| {
+            b.custom_id(
+                serde_json::to_string(&QueuePager {
+                    nonce: 0,
+                    queue_page: 0,
+                })
+                .unwrap(),
+            )
+            .style(ButtonStyle::Primary)
+            .label("⏪")
+            .disabled(self.queue_page == 0)
+        })
+        .create_button(|b| {
+            b.custom_id(
+                serde_json::to_string(&QueuePager {
+                    nonce: 1,
+                    queue_page: self.queue_page.saturating_sub(1),
+                })
+                .unwrap(),
+            )
+            .style(ButtonStyle::Secondary)
+            .label("◀️")
+            .disabled(self.queue_page == 0)
+        })
+        .create_button(|b| {
+            b.custom_id("qpid")
+                .style(ButtonStyle::Success)
+                .label(format!("Page {}", self.queue_page + 1))
+                .disabled(true)
+        })
+        .create_button(|b| {
+            b.custom_id(
+                serde_json::to_string(&QueuePager {
+                    nonce: 2,
+                    queue_page: self.queue_page.saturating_add(1),
+                })
+                .unwrap(),
+            )
+            .style(ButtonStyle::Secondary)
+            .label("▶️")
+            .disabled(self.queue_page >= max_page)
+        })
+        .create_button(|b| {
+            b.custom_id(
+                serde_json::to_string(&QueuePager {
+                    nonce: 3,
+                    queue_page: max_page,
+                })
+                .unwrap(),
+            )
+            .style(ButtonStyle::Primary)
+            .label("⏩")
+            .disabled(self.queue_page >= max_page)
+        });
+
+        row
+    }
+
+    fn embed(&self, entries: &[QueueEntry]) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+
+        embed
+            .color(THEME_COLOR)
+            .title("Queue")
+            .description(format!("**{}** sounds queued:", entries.len()))
+            .fields(
+                entries
+                    .iter()
+                    .enumerate()
+                    .skip(self.queue_page * PAGE_SIZE)
+                    .take(PAGE_SIZE)
+                    .map(|(index, entry)| {
+                        let duration = entry
+                            .info
+                            .as_ref()
+                            .and_then(|i| i.duration)
+                            .map(format_duration);
+
+                        let timing = match (entry.elapsed, duration) {
+                            (Some(elapsed), Some(duration)) => {
+                                format!("Now playing · {} / {}", format_duration(elapsed), duration)
+                            }
+                            (Some(elapsed), None) => {
+                                format!("Now playing · {}", format_duration(elapsed))
+                            }
+                            (None, Some(duration)) => duration,
+                            (None, None) => String::new(),
+                        };
+
+                        match &entry.info {
+                            Some(info) => (
+                                format!("{}. {}", index + 1, info.name),
+                                format!(
+                                    "ID: `{}` · {}\nQueued by <@{}>",
+                                    info.sound_id, timing, info.user_id
+                                ),
+                                false,
+                            ),
+
+                            None => (format!("{}. Unknown sound", index + 1), timing, false),
+                        }
+                    }),
+            );
+
+        embed
+    }
+
+    pub async fn handle_interaction(
+        ctx: &serenity_prelude::Context,
+        interaction: &MessageComponentInteraction,
+    ) -> Result<(), Error> {
+        let guild_id = interaction.guild_id.unwrap();
+
+        let pager = serde_json::from_str::<Self>(&interaction.data.custom_id)?;
+        let entries = match guild_queue(ctx, guild_id).await {
+            Some(queue) => Self::entries(&queue).await,
+
+            None => vec![],
+        };
+
+        // the queue may have shrunk since the page was shown
+        let max_page = Self::max_page(entries.len());
+        let pager = QueuePager {
+            nonce: 0,
+            queue_page: pager.queue_page.min(max_page),
+        };
+
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.ephemeral(true)
+                            .add_embed(pager.embed(&entries))
+                            .components(|c| c.add_action_row(pager.create_action_row(max_page)))
+                    })
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn reply(&self, ctx: Context<'_>, entries: &[QueueEntry]) -> Result<(), Error> {
+        let max_page = Self::max_page(entries.len());
+
+        ctx.send(|r| {
+            r.ephemeral(true)
+                .embed(|e| {
+                    *e = self.embed(entries);
+                    e
+                })
+                .components(|c| c.add_action_row(self.create_action_row(max_page)))
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// View the sounds in the queue
+#[poise::command(slash_command, rename = "view", guild_only = true)]
+pub async fn view_queue(ctx: Context<'_>) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            let pager = QueuePager {
+                nonce: 0,
+                queue_page: 0,
+            };
+
+            pager.reply(ctx, &QueuePager::entries(&queue).await).await?;
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Skip the sound playing from the queue
+#[poise::command(
+    slash_command,
+    rename = "skip",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn skip_queue(ctx: Context<'_>) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            let _ = queue.skip();
+
+            ctx.say("Skipped").await?;
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove a sound from the queue
+#[poise::command(
+    slash_command,
+    rename = "remove",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn remove_from_queue(
+    ctx: Context<'_>,
+    #[description = "Position of the sound in the queue"]
+    #[min = 1]
+    position: usize,
+) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            let removed = if position == 1 {
+                let current = queue.current();
+                let _ = queue.skip();
+
+                current
+            } else {
+                // the sound is removed from the queue before it is stopped, so that stopping it
+                // doesn't advance the queue
+                queue.dequeue(position - 1).map(|queued| {
+                    let handle = queued.handle();
+                    let _ = handle.stop();
+
+                    handle
+                })
+            };
+
+            match removed {
+                Some(handle) => {
+                    let name = TrackInfo::of(&handle)
+                        .await
+                        .map_or_else(|| "Sound".to_string(), |i| i.name);
+
+                    ctx.say(format!("{} was removed from the queue", name))
+                        .await?;
+                }
+
+                None => {
+                    ctx.say(format!(
+                        "There is no sound at position {} in the queue",
+                        position
+                    ))
+                    .await?;
+                }
+            }
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shuffle the sounds waiting in the queue
+#[poise::command(
+    slash_command,
+    rename = "shuffle",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn shuffle_queue(ctx: Context<'_>) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            // the sound currently playing stays at the front
+            queue.modify_queue(|q| {
+                if q.len() > 2 {
+                    q.make_contiguous()[1..].shuffle(&mut rand::thread_rng());
+                }
+            });
+
+            ctx.say("Queue shuffled").await?;
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move a sound to a different position in the queue
+#[poise::command(
+    slash_command,
+    rename = "move",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn move_in_queue(
+    ctx: Context<'_>,
+    #[description = "Current position of the sound"]
+    #[min = 2]
+    from: usize,
+    #[description = "New position of the sound"]
+    #[min = 2]
+    to: usize,
+) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            // the sound currently playing can't be moved, and nothing can be moved in front of it
+            let moved = queue.modify_queue(|q| {
+                if from < 2 || to < 2 || from > q.len() || to > q.len() {
+                    return false;
+                }
+
+                if let Some(queued) = q.remove(from - 1) {
+                    q.insert(to - 1, queued);
+                }
+
+                true
+            });
+
+            if moved {
+                ctx.say(format!(
+                    "Moved the sound at position {} to position {}",
+                    from, to
+                ))
+                .await?;
+            } else {
+                ctx.say(format!(
+                    "Positions must be between 2 and {}. The sound currently playing can't be moved",
+                    queue.len()
+                ))
+                .await?;
+            }
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove all sounds waiting in the queue, leaving the current sound playing
+#[poise::command(
+    slash_command,
+    rename = "clear",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn clear_queue(ctx: Context<'_>) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            let removed = queue.modify_queue(|q| q.split_off(q.len().min(1)));
+
+            for queued in &removed {
+                let _ = queued.handle().stop();
+            }
+
+            ctx.say(format!("Removed {} sounds from the queue", removed.len()))
+                .await?;
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pause the queue
+#[poise::command(
+    slash_command,
+    rename = "pause",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn pause_queue(ctx: Context<'_>) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            let _ = queue.pause();
+
+            ctx.say("Queue paused. Use `/queue resume` to continue")
+                .await?;
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resume the queue
+#[poise::command(
+    slash_command,
+    rename = "resume",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn resume_queue(ctx: Context<'_>) -> Result<(), Error> {
+    match guild_queue(ctx.discord(), ctx.guild_id().unwrap()).await {
+        Some(queue) => {
+            let _ = queue.resume();
+
+            ctx.say("Queue resumed").await?;
+        }
+
+        None => {
+            ctx.say(NOTHING_QUEUED).await?;
+        }
+    }
+
+    Ok(())
+}
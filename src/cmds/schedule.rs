@@ -0,0 +1,357 @@
+use chrono::{NaiveDateTime, Utc};
+use poise::serenity_prelude::GuildChannel;
+
+use crate::{
+    cmds::{autocomplete_sound, can_manage_guild},
+    consts::THEME_COLOR,
+    models::{
+        guild_data::CtxGuildData,
+        schedule::{parse_recurrence, Schedule},
+        sound::SoundCtx,
+    },
+    Context, Error,
+};
+
+const MAX_SCHEDULES: usize = 25;
+
+/// Parse a time written as `YYYY-MM-DD HH:MM` in UTC, a unix timestamp, or a Discord timestamp
+/// like `<t:1699999980:F>`
+fn parse_time(time: &str) -> Result<NaiveDateTime, String> {
+    let time = time.trim();
+    let timestamp = time
+        .strip_prefix("<t:")
+        .and_then(|t| t.strip_suffix('>'))
+        .map(|t| t.split(':').next().unwrap_or(t))
+        .unwrap_or(time);
+
+    match timestamp.parse::<i64>() {
+        Ok(timestamp) => NaiveDateTime::from_timestamp_opt(timestamp, 0)
+            .ok_or_else(|| format!("Invalid timestamp `{}`", timestamp)),
+
+        Err(_) => NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").map_err(|_| {
+            format!(
+                "Invalid time `{}`. Use `YYYY-MM-DD HH:MM` in UTC, or a timestamp",
+                time
+            )
+        }),
+    }
+}
+
+/// Play sounds at a set time or on repeat
+#[poise::command(
+    slash_command,
+    rename = "schedule",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn schedule(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Schedule sounds to play in a voice channel
+#[poise::command(
+    slash_command,
+    rename = "add",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn add_schedule(
+    ctx: Context<'_>,
+    #[description = "Name or ID of sound to play"]
+    #[autocomplete = "autocomplete_sound"]
+    sound: String,
+    #[description = "Time to play, as \"YYYY-MM-DD HH:MM\" in UTC or a timestamp (default: now)"]
+    at: Option<String>,
+    #[description = "Repeat on a cron schedule in UTC, e.g. \"0 * * * *\" for every hour"]
+    repeat: Option<String>,
+    #[description = "Channel to play in (default: your current voice channel)"]
+    #[channel_types("Voice")]
+    channel: Option<GuildChannel>,
+    #[description = "Name or ID of sound to queue after"]
+    #[autocomplete = "autocomplete_sound"]
+    then_1: Option<String>,
+    #[description = "Name or ID of sound to queue after"]
+    #[autocomplete = "autocomplete_sound"]
+    then_2: Option<String>,
+    #[description = "Name or ID of sound to queue after"]
+    #[autocomplete = "autocomplete_sound"]
+    then_3: Option<String>,
+    #[description = "Name or ID of sound to queue after"]
+    #[autocomplete = "autocomplete_sound"]
+    then_4: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let now = Utc::now().naive_utc();
+
+    let channel_id = match channel {
+        Some(channel) => channel.id,
+
+        None => {
+            let current_channel = ctx.guild().and_then(|guild| {
+                guild
+                    .voice_states
+                    .get(&ctx.author().id)
+                    .and_then(|voice_state| voice_state.channel_id)
+            });
+
+            match current_channel {
+                Some(channel_id) => channel_id,
+
+                None => {
+                    ctx.say("You are not in a voice chat! Join one, or choose a channel")
+                        .await?;
+
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let recurrence = match repeat.as_deref().map(parse_recurrence).transpose() {
+        Ok(recurrence) => recurrence,
+
+        Err(reason) => {
+            ctx.say(reason).await?;
+
+            return Ok(());
+        }
+    };
+
+    let next_run = match (at.as_deref().map(parse_time).transpose(), &recurrence) {
+        (Err(reason), _) => {
+            ctx.say(reason).await?;
+
+            return Ok(());
+        }
+
+        (Ok(Some(time)), _) => time,
+
+        (Ok(None), Some((_, recurrence))) => match recurrence.upcoming(Utc).next() {
+            Some(next) => next.naive_utc(),
+
+            None => {
+                ctx.say("That repeat never runs").await?;
+
+                return Ok(());
+            }
+        },
+
+        (Ok(None), None) => now,
+    };
+
+    if next_run < now - chrono::Duration::minutes(1) {
+        ctx.say("That time has already passed").await?;
+
+        return Ok(());
+    }
+
+    if Schedule::guild_schedules(guild_id.0, &ctx.data().database)
+        .await?
+        .len()
+        >= MAX_SCHEDULES
+    {
+        ctx.say(format!(
+            "This server already has {} schedules. Cancel one with `/schedule cancel` first",
+            MAX_SCHEDULES
+        ))
+        .await?;
+
+        return Ok(());
+    }
+
+    let max_duration = ctx
+        .guild_data(guild_id)
+        .await?
+        .read()
+        .await
+        .max_play_duration;
+
+    let mut sounds = vec![];
+    for query in [Some(sound), then_1, then_2, then_3, then_4]
+        .iter()
+        .flatten()
+    {
+        let search = ctx
+            .data()
+            .search_for_sound(query, guild_id, ctx.author().id, true)
+            .await?;
+
+        match search.first() {
+            Some(sound) if sound.too_long(max_duration) => {
+                ctx.say(format!(
+                    "Sound {} is too long to play in this server (max. {}s)",
+                    sound.name,
+                    max_duration.unwrap_or(0)
+                ))
+                .await?;
+
+                return Ok(());
+            }
+
+            Some(sound) => sounds.push(sound.clone()),
+
+            None => {
+                ctx.say(format!("Couldn't find sound `{}`", query)).await?;
+
+                return Ok(());
+            }
+        }
+    }
+
+    let sound_ids = sounds.iter().map(|s| s.id).collect::<Vec<_>>();
+    let id = Schedule::create(
+        guild_id.0,
+        channel_id.0,
+        ctx.author().id.0,
+        next_run,
+        recurrence
+            .as_ref()
+            .map(|(expression, _)| expression.as_str()),
+        &sound_ids,
+        &ctx.data().database,
+    )
+    .await?;
+
+    let names = sounds
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    ctx.say(format!(
+        "Schedule {} will play {} in <#{}> <t:{}:R>{}",
+        id,
+        names,
+        channel_id,
+        next_run.timestamp(),
+        if recurrence.is_some() {
+            ", then repeat"
+        } else {
+            ""
+        }
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// View the schedules on this server
+#[poise::command(
+    slash_command,
+    rename = "list",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn list_schedules(ctx: Context<'_>) -> Result<(), Error> {
+    let schedules =
+        Schedule::guild_schedules(ctx.guild_id().unwrap().0, &ctx.data().database).await?;
+
+    if schedules.is_empty() {
+        ctx.say("There are no schedules on this server").await?;
+
+        return Ok(());
+    }
+
+    let mut lines = vec![];
+    for schedule in &schedules {
+        let names = schedule
+            .sounds(&ctx.data().database)
+            .await?
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        lines.push(format!(
+            "**{}** · {} in <#{}>\nNext <t:{}:R>{}",
+            schedule.id,
+            names,
+            schedule.channel_id,
+            schedule.next_run.timestamp(),
+            schedule
+                .recurrence
+                .as_ref()
+                .map_or_else(String::new, |r| format!(" · repeats `{}`", r))
+        ));
+    }
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Schedules")
+                .color(THEME_COLOR)
+                .description(lines.join("\n\n"))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Cancel a schedule
+#[poise::command(
+    slash_command,
+    rename = "cancel",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn cancel_schedule(
+    ctx: Context<'_>,
+    #[description = "ID of the schedule, from /schedule list"] id: u32,
+) -> Result<(), Error> {
+    let schedules =
+        Schedule::guild_schedules(ctx.guild_id().unwrap().0, &ctx.data().database).await?;
+
+    match schedules.iter().find(|s| s.id == id) {
+        Some(schedule) => {
+            if schedule.user_id != ctx.author().id.0 && !can_manage_guild(ctx).await {
+                ctx.say("You can only cancel schedules you created").await?;
+            } else {
+                schedule.delete(&ctx.data().database).await?;
+
+                ctx.say(format!("Schedule {} has been cancelled", id))
+                    .await?;
+            }
+        }
+
+        None => {
+            ctx.say("No schedule found with that ID").await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn time_utc() {
+        assert_eq!(
+            parse_time(" 2026-03-01 18:30 "),
+            Ok(time("2026-03-01 18:30"))
+        );
+    }
+
+    #[test]
+    fn time_unix_timestamp() {
+        assert_eq!(parse_time("1699999980"), Ok(time("2023-11-14 22:13")));
+    }
+
+    #[test]
+    fn time_discord_timestamp() {
+        assert_eq!(parse_time("<t:1699999980>"), Ok(time("2023-11-14 22:13")));
+        assert_eq!(parse_time("<t:1699999980:F>"), Ok(time("2023-11-14 22:13")));
+    }
+
+    #[test]
+    fn time_invalid() {
+        assert!(parse_time("tomorrow").is_err());
+        assert!(parse_time("2026-13-01 18:30").is_err());
+        assert!(parse_time("<t:abc:F>").is_err());
+    }
+}
@@ -11,8 +11,9 @@ use poise::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    audio::format_duration,
     consts::THEME_COLOR,
-    models::sound::{Sound, SoundCtx},
+    models::sound::{normalise_tag, Sound, SoundCtx},
     Context, Data, Error,
 };
 
@@ -25,7 +26,17 @@ fn format_search_results<'a>(search_results: Vec<Sound>) -> CreateReply<'a> {
     let field_iter = search_results
         .iter()
         .take(25)
-        .map(|item| (&item.name, format!("ID: {}", item.id), true))
+        .map(|item| {
+            (
+                &item.name,
+                match item.duration {
+                    Some(duration) => format!("ID: {} ({})", item.id, format_duration(duration)),
+
+                    None => format!("ID: {}", item.id),
+                },
+                true,
+            )
+        })
         .filter(|item| {
             current_character_count += item.0.len() + item.1.len();
 
@@ -182,9 +193,12 @@ impl SoundPager {
                 (
                     s.name.as_str(),
                     format!(
-                        "ID: `{}`\n{}",
+                        "ID: `{}`\n{}{}",
                         s.id,
-                        if s.public { "*Public*" } else { "*Private*" }
+                        if s.public { "*Public*" } else { "*Private*" },
+                        s.duration
+                            .map(|d| format!(" · {}", format_duration(d)))
+                            .unwrap_or_default()
                     ),
                     true,
                 )
@@ -258,12 +272,43 @@ impl SoundPager {
 )]
 pub async fn search_sounds(
     ctx: Context<'_>,
-    #[description = "Sound name to search for"] query: String,
+    #[description = "Sound name to search for. Filter by tag with tag:name"] query: String,
 ) -> Result<(), Error> {
-    let search_results = ctx
-        .data()
-        .search_for_sound(&query, ctx.guild_id().unwrap(), ctx.author().id, false)
-        .await?;
+    let mut tags = vec![];
+    let mut words = vec![];
+
+    for word in query.split_whitespace() {
+        match word.strip_prefix("tag:").map(normalise_tag) {
+            Some(Some(tag)) => {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+
+            Some(None) => {
+                ctx.say(format!("Invalid tag `{}`", word)).await?;
+
+                return Ok(());
+            }
+
+            None => words.push(word),
+        }
+    }
+
+    let search_results = if tags.is_empty() {
+        ctx.data()
+            .search_for_sound(&query, ctx.guild_id().unwrap(), ctx.author().id, false)
+            .await?
+    } else {
+        ctx.data()
+            .search_tagged_sounds(
+                &words.join(" "),
+                &tags,
+                ctx.guild_id().unwrap(),
+                ctx.author().id,
+            )
+            .await?
+    };
 
     ctx.send(|m| {
         *m = format_search_results(search_results);
@@ -280,7 +325,7 @@ pub async fn show_random_sounds(ctx: Context<'_>) -> Result<(), Error> {
     let search_results = sqlx::query_as_unchecked!(
         Sound,
         "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE public = 1
     ORDER BY rand()
@@ -1,12 +1,13 @@
-use poise::serenity_prelude::{GuildId, User};
+use poise::serenity_prelude::{GuildId, Role, User};
 
 use crate::{
     cmds::autocomplete_sound,
     models::{
-        guild_data::{AllowGreet, CtxGuildData},
+        guild_data::{AllowGreet, CtxGuildData, PlaybackMode},
         join_sound::JoinSoundCtx,
         sound::SoundCtx,
     },
+    utils::{cancel_idle_timer, start_idle_timer},
     Context, Error,
 };
 
@@ -38,6 +39,276 @@ pub async fn change_volume(
     Ok(())
 }
 
+#[derive(poise::ChoiceParameter)]
+pub enum DurationLimit {
+    #[name = "Played sounds"]
+    Play,
+    #[name = "Greet sounds"]
+    Greet,
+}
+
+/// Set the maximum length of sounds played on this server
+#[poise::command(
+    slash_command,
+    rename = "maxduration",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn change_max_duration(
+    ctx: Context<'_>,
+    #[description = "Kind of sound to limit"] kind: DurationLimit,
+    #[description = "Maximum length in seconds (default: no limit)"] seconds: Option<u32>,
+) -> Result<(), Error> {
+    let seconds = seconds.filter(|s| *s > 0);
+    let guild_data = ctx.guild_data(ctx.guild_id().unwrap()).await?;
+
+    {
+        let mut write = guild_data.write().await;
+
+        match kind {
+            DurationLimit::Play => write.max_play_duration = seconds,
+            DurationLimit::Greet => write.max_greet_duration = seconds,
+        }
+    }
+
+    guild_data.read().await.commit(&ctx.data().database).await?;
+
+    let kind_name = match kind {
+        DurationLimit::Play => "Played sounds",
+        DurationLimit::Greet => "Greet sounds",
+    };
+
+    match seconds {
+        Some(seconds) => {
+            ctx.say(format!(
+                "{} are now limited to {} seconds",
+                kind_name, seconds
+            ))
+            .await?;
+        }
+
+        None => {
+            ctx.say(format!("{} are no longer limited in length", kind_name))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Set how long the bot stays in voice after the last sound finishes
+#[poise::command(
+    slash_command,
+    rename = "idletimeout",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn change_idle_timeout(
+    ctx: Context<'_>,
+    #[description = "Seconds to wait before leaving (default: stay connected)"]
+    #[min = 10]
+    seconds: Option<u32>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_data = ctx.guild_data(guild_id).await?;
+
+    guild_data.write().await.idle_timeout = seconds;
+    guild_data.read().await.commit(&ctx.data().database).await?;
+
+    // a bot already sitting idle in voice has no track left to end and start the new timer
+    if ctx.data().songbird.get(guild_id).is_some() {
+        start_idle_timer(ctx.data(), guild_id).await;
+    } else {
+        cancel_idle_timer(ctx.data(), guild_id);
+    }
+
+    match seconds {
+        Some(seconds) => {
+            ctx.say(format!(
+                "The bot will leave voice after {} seconds without playing",
+                seconds
+            ))
+            .await?;
+        }
+
+        None => {
+            ctx.say("The bot will stay in voice until disconnected")
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Choose what happens when a sound is played while another is playing
+#[poise::command(
+    slash_command,
+    rename = "playbackmode",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn change_playback_mode(
+    ctx: Context<'_>,
+    #[description = "What new sounds should do"] mode: PlaybackMode,
+    #[description = "Maximum sounds playing at once, for \"Overlap with limit\" (default: 3)"]
+    #[min = 1]
+    #[max = 25]
+    limit: Option<u8>,
+) -> Result<(), Error> {
+    let guild_data = ctx.guild_data(ctx.guild_id().unwrap()).await?;
+
+    {
+        let mut write = guild_data.write().await;
+
+        write.playback_mode = mode;
+        if let Some(limit) = limit {
+            write.overlap_limit = limit;
+        }
+    }
+
+    let read = guild_data.read().await;
+    read.commit(&ctx.data().database).await?;
+
+    let description = match read.playback_mode {
+        PlaybackMode::Overlap => "New sounds will play over sounds already playing".to_string(),
+        PlaybackMode::Interrupt => "New sounds will stop sounds already playing".to_string(),
+        PlaybackMode::Queue => "New sounds will wait for sounds already playing".to_string(),
+        PlaybackMode::Limited => format!(
+            "New sounds will play over sounds already playing, up to {} at once",
+            read.overlap_limit
+        ),
+    };
+
+    ctx.say(description).await?;
+
+    Ok(())
+}
+
+/// Limit how often each user can play sounds
+#[poise::command(
+    slash_command,
+    rename = "ratelimit",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn rate_limit(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Set how many sounds each user can play in a length of time
+#[poise::command(
+    slash_command,
+    rename = "set",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn set_rate_limit(
+    ctx: Context<'_>,
+    #[description = "Number of sounds each user can play"]
+    #[min = 1]
+    #[max = 100]
+    plays: u8,
+    #[description = "Length of time in seconds (default: 10)"]
+    #[min = 1]
+    #[max = 3600]
+    seconds: Option<u32>,
+) -> Result<(), Error> {
+    let guild_data = ctx.guild_data(ctx.guild_id().unwrap()).await?;
+
+    {
+        let mut write = guild_data.write().await;
+
+        write.rate_limit_plays = Some(plays);
+        if let Some(seconds) = seconds {
+            write.rate_limit_window = seconds;
+        }
+    }
+
+    let read = guild_data.read().await;
+    read.commit(&ctx.data().database).await?;
+
+    ctx.say(format!(
+        "Users can play {} sounds every {} seconds",
+        plays, read.rate_limit_window
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Let users play sounds as often as they like
+#[poise::command(
+    slash_command,
+    rename = "disable",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn disable_rate_limit(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_data = ctx.guild_data(ctx.guild_id().unwrap()).await?;
+
+    guild_data.write().await.rate_limit_plays = None;
+    guild_data.read().await.commit(&ctx.data().database).await?;
+
+    ctx.say("Users can play sounds as often as they like")
+        .await?;
+
+    Ok(())
+}
+
+/// Stop a role from being rate limited
+#[poise::command(
+    slash_command,
+    rename = "exempt",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn exempt_role(
+    ctx: Context<'_>,
+    #[description = "Role to exempt"] role: Role,
+) -> Result<(), Error> {
+    let guild_data = ctx.guild_data(ctx.guild_id().unwrap()).await?;
+
+    guild_data
+        .read()
+        .await
+        .add_exempt_role(role.id.0, &ctx.data().database)
+        .await?;
+
+    ctx.say(format!("Members of {} won't be rate limited", role.name))
+        .await?;
+
+    Ok(())
+}
+
+/// Rate limit a role that was exempt
+#[poise::command(
+    slash_command,
+    rename = "unexempt",
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn unexempt_role(
+    ctx: Context<'_>,
+    #[description = "Role to stop exempting"] role: Role,
+) -> Result<(), Error> {
+    let guild_data = ctx.guild_data(ctx.guild_id().unwrap()).await?;
+
+    let removed = guild_data
+        .read()
+        .await
+        .remove_exempt_role(role.id.0, &ctx.data().database)
+        .await?;
+
+    if removed {
+        ctx.say(format!("Members of {} will be rate limited", role.name))
+            .await?;
+    } else {
+        ctx.say(format!("{} isn't exempt", role.name)).await?;
+    }
+
+    Ok(())
+}
+
 /// Manage greet sounds
 #[poise::command(slash_command, rename = "greet", guild_only = true)]
 pub async fn greet_sound(_ctx: Context<'_>) -> Result<(), Error> {
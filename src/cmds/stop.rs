@@ -1,6 +1,9 @@
 use songbird;
 
-use crate::{Context, Error};
+use crate::{
+    utils::{end_track_loop, leave_call, live_tracks},
+    Context, Error,
+};
 
 /// Stop the bot from playing and clear the play queue
 #[poise::command(
@@ -24,11 +27,38 @@ pub async fn stop_playing(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Stop looping sounds, leaving other sounds playing
+#[poise::command(
+    slash_command,
+    rename = "endloop",
+    default_member_permissions = "SPEAK",
+    guild_only = true
+)]
+pub async fn end_loop(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let looping = live_tracks(&ctx.data().looping_tracks, guild_id).await;
+
+    // the sounds finish what they are playing, rather than being cut off
+    for handle in &looping {
+        end_track_loop(handle).await;
+    }
+    ctx.data().looping_tracks.remove(&guild_id);
+
+    if looping.is_empty() {
+        ctx.say("No sounds are looping").await?;
+    } else {
+        ctx.say("👍").await?;
+    }
+
+    Ok(())
+}
+
 /// Disconnect the bot
 #[poise::command(slash_command, default_member_permissions = "SPEAK", guild_only = true)]
 pub async fn disconnect(ctx: Context<'_>) -> Result<(), Error> {
-    let songbird = songbird::get(ctx.discord()).await.unwrap();
-    let _ = songbird.leave(ctx.guild_id().unwrap()).await;
+    let guild_id = ctx.guild_id().unwrap();
+
+    leave_call(ctx.data(), guild_id).await;
 
     ctx.say("👍").await?;
 
@@ -7,6 +7,27 @@ lazy_static! {
         .unwrap_or_else(|_| "2097152".to_string())
         .parse::<u64>()
         .unwrap();
+    pub static ref UPLOAD_MAX_DURATION: Option<f64> = env::var("UPLOAD_MAX_DURATION")
+        .ok()
+        .map(|d| d.parse::<f64>().unwrap());
+    pub static ref CACHING_LOCATION: String =
+        env::var("CACHING_LOCATION").unwrap_or_else(|_| "/tmp/soundfx-cache".to_string());
+    pub static ref CACHE_MAX_SIZE: u64 = env::var("CACHE_MAX_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse::<u64>()
+        .unwrap();
+    pub static ref MEMORY_CACHE_MAX_SIZE: u64 = env::var("MEMORY_CACHE_MAX_SIZE")
+        .unwrap_or_else(|_| "134217728".to_string())
+        .parse::<u64>()
+        .unwrap();
+    pub static ref MEMORY_CACHE_ADMIT_PLAYS: u32 = env::var("MEMORY_CACHE_ADMIT_PLAYS")
+        .unwrap_or_else(|_| "3".to_string())
+        .parse::<u32>()
+        .unwrap();
+    pub static ref LOUDNESS_TARGET: f64 = env::var("LOUDNESS_TARGET")
+        .unwrap_or_else(|_| "-16".to_string())
+        .parse::<f64>()
+        .unwrap();
     pub static ref MAX_SOUNDS: u32 = env::var("MAX_SOUNDS")
         .unwrap_or_else(|_| "8".to_string())
         .parse::<u32>()
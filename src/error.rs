@@ -1,13 +1,179 @@
-use std::fmt::Formatter;
+use std::{fmt::Formatter, time::Duration};
+
+use log::{error, warn};
+
+use crate::{audio::format_duration, Data, Error};
+
+const DATABASE_MESSAGE: &str = "Something went wrong while saving. Please try again later.";
 
 #[derive(Debug)]
 pub enum ErrorTypes {
-    InvalidFile,
+    /// ffmpeg couldn't decode any audio from the file. Holds ffmpeg's explanation
+    UnsupportedCodec(String),
+    /// The encoded audio reached the upload size limit
+    TooLarge {
+        max_size: u64,
+    },
+    TooLong {
+        duration: f64,
+        max_duration: f64,
+    },
+    /// The audio has no measurable loudness
+    Silent,
+    DownloadFailed {
+        url: String,
+        details: String,
+    },
+    /// ffmpeg or ffprobe failed for a reason that isn't the file's fault
+    FfmpegFailed {
+        status: Option<i32>,
+        stderr: String,
+    },
+    Database(sqlx::Error),
+    MissingSource,
+    InvalidTrim(String),
+    /// The user has played too many sounds recently. Holds the time until they can play again
+    RateLimited(Duration),
+}
+
+impl ErrorTypes {
+    /// A message explaining the error that is suitable to show to users
+    pub fn user_message(&self) -> String {
+        match self {
+            ErrorTypes::UnsupportedCodec(_) => {
+                "The file isn't in an audio format that can be read. Try converting it to MP3 or OGG first.".to_string()
+            }
+            ErrorTypes::TooLarge { max_size } => format!(
+                "The sound is too large once encoded (max. {:.1}MB). Use `start` and `end` to upload a shorter part of it.",
+                *max_size as f64 / 1_048_576.0
+            ),
+            ErrorTypes::TooLong {
+                duration,
+                max_duration,
+            } => format!(
+                "The sound is too long ({}, max. {}). Use `start` and `end` to upload a shorter part of it.",
+                format_duration(*duration),
+                format_duration(*max_duration)
+            ),
+            ErrorTypes::Silent => "The sound is completely silent.".to_string(),
+            ErrorTypes::DownloadFailed { .. } => {
+                "The file couldn't be downloaded from Discord. Please try again.".to_string()
+            }
+            ErrorTypes::FfmpegFailed { .. } => {
+                "Something went wrong while processing the audio. Please try again later.".to_string()
+            }
+            ErrorTypes::Database(_) => DATABASE_MESSAGE.to_string(),
+            ErrorTypes::MissingSource => "The audio for this sound is missing.".to_string(),
+            ErrorTypes::InvalidTrim(reason) => reason.clone(),
+            ErrorTypes::RateLimited(retry_after) => format!(
+                "You're playing sounds too quickly! Try again in {}s.",
+                retry_after.as_secs_f64().ceil() as u64
+            ),
+        }
+    }
+
+    /// Whether the error is a problem with the bot rather than with what the user asked for
+    pub fn is_internal(&self) -> bool {
+        matches!(
+            self,
+            ErrorTypes::DownloadFailed { .. }
+                | ErrorTypes::FfmpegFailed { .. }
+                | ErrorTypes::Database(_)
+                | ErrorTypes::MissingSource
+        )
+    }
+}
+
+impl From<sqlx::Error> for ErrorTypes {
+    fn from(e: sqlx::Error) -> Self {
+        ErrorTypes::Database(e)
+    }
 }
 
 impl std::error::Error for ErrorTypes {}
 impl std::fmt::Display for ErrorTypes {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ErrorTypes: InvalidFile")
+        match self {
+            ErrorTypes::UnsupportedCodec(details) => {
+                write!(f, "ErrorTypes: UnsupportedCodec: {}", details)
+            }
+            ErrorTypes::TooLarge { max_size } => {
+                write!(f, "ErrorTypes: TooLarge: max {} bytes", max_size)
+            }
+            ErrorTypes::TooLong {
+                duration,
+                max_duration,
+            } => write!(
+                f,
+                "ErrorTypes: TooLong: {}s, max {}s",
+                duration, max_duration
+            ),
+            ErrorTypes::Silent => write!(f, "ErrorTypes: Silent"),
+            ErrorTypes::DownloadFailed { url, details } => {
+                write!(f, "ErrorTypes: DownloadFailed: {}: {}", url, details)
+            }
+            ErrorTypes::FfmpegFailed { status, stderr } => write!(
+                f,
+                "ErrorTypes: FfmpegFailed (status {:?}): {}",
+                status, stderr
+            ),
+            ErrorTypes::Database(e) => write!(f, "ErrorTypes: Database: {}", e),
+            ErrorTypes::MissingSource => write!(f, "ErrorTypes: MissingSource"),
+            ErrorTypes::InvalidTrim(reason) => write!(f, "ErrorTypes: InvalidTrim: {}", reason),
+            ErrorTypes::RateLimited(retry_after) => {
+                write!(f, "ErrorTypes: RateLimited: {:?}", retry_after)
+            }
+        }
+    }
+}
+
+/// Find the message to show a user for any error, logging errors that the user can't fix
+pub fn user_message(e: &Error) -> String {
+    match e.downcast_ref::<ErrorTypes>() {
+        Some(error_type) => {
+            if error_type.is_internal() {
+                warn!("{}", error_type);
+            }
+
+            error_type.user_message()
+        }
+
+        None => {
+            error!("Unexpected error: {:?}", e);
+
+            match e.downcast_ref::<sqlx::Error>() {
+                Some(_) => DATABASE_MESSAGE.to_string(),
+
+                None => "An unexpected error occurred. Please try again later.".to_string(),
+            }
+        }
+    }
+}
+
+pub async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
+    match error {
+        poise::FrameworkError::Command { error, ctx } => {
+            warn!(
+                "Command /{} failed in guild {:?}",
+                ctx.command().qualified_name,
+                ctx.guild_id()
+            );
+
+            let message = user_message(&error);
+
+            if let Err(e) = ctx.send(|m| m.content(message).ephemeral(true)).await {
+                warn!("Failed to send error message: {:?}", e);
+            }
+        }
+
+        poise::FrameworkError::Listener { error, event, .. } => {
+            error!("Error handling event {}: {:?}", event.name(), error);
+        }
+
+        error => {
+            if let Err(e) = poise::builtins::on_error(error).await {
+                warn!("Failed to handle error: {:?}", e);
+            }
+        }
     }
 }
@@ -1,5 +1,6 @@
 use std::{collections::HashMap, env};
 
+use log::warn;
 use poise::serenity_prelude::{
     model::{
         application::interaction::{Interaction, InteractionResponseType},
@@ -10,13 +11,15 @@ use poise::serenity_prelude::{
 };
 
 use crate::{
-    cmds::search::SoundPager,
+    audio::Effects,
+    cmds::{now_playing::PanelButton, queue::QueuePager, search::SoundPager},
+    error,
     models::{
         guild_data::{AllowGreet, CtxGuildData},
         join_sound::JoinSoundCtx,
         sound::Sound,
     },
-    utils::{join_channel, play_audio, play_from_query},
+    utils::{join_channel, leave_call, play_audio, play_from_query, Segment},
     Data, Error,
 };
 
@@ -71,9 +74,7 @@ pub async fn listener(ctx: &Context, event: &poise::Event<'_>, data: &Data) -> R
                     if let Some(channel_id) = past_state.channel_id {
                         if let Some(Channel::Guild(channel)) = channel_id.to_channel_cached(&ctx) {
                             if channel.members(&ctx).await.map(|m| m.len()).unwrap_or(0) <= 1 {
-                                let songbird = songbird::get(ctx).await.unwrap();
-
-                                let _ = songbird.remove(guild_id).await;
+                                leave_call(data, guild_id).await;
                             }
                         }
                     }
@@ -83,15 +84,9 @@ pub async fn listener(ctx: &Context, event: &poise::Event<'_>, data: &Data) -> R
                     let guild_data_opt = data.guild_data(guild.id).await;
 
                     if let Ok(guild_data) = guild_data_opt {
-                        let volume;
-                        let allowed_greets;
-
-                        {
-                            let read = guild_data.read().await;
-
-                            volume = read.volume;
-                            allowed_greets = read.allow_greets;
-                        }
+                        let guild_data = guild_data.read().await.clone();
+                        let allowed_greets = guild_data.allow_greets;
+                        let max_duration = guild_data.max_greet_duration;
 
                         if allowed_greets != AllowGreet::Disabled {
                             if let Some(join_id) = data
@@ -102,30 +97,46 @@ pub async fn listener(ctx: &Context, event: &poise::Event<'_>, data: &Data) -> R
                                 )
                                 .await
                             {
-                                let mut sound = sqlx::query_as_unchecked!(
+                                let sound = sqlx::query_as_unchecked!(
                                     Sound,
                                     "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE id = ?
                                         ",
                                     join_id
                                 )
-                                .fetch_one(&data.database)
-                                .await
-                                .unwrap();
+                                .fetch_optional(&data.database)
+                                .await?;
+
+                                // the greet sound may have been deleted since it was set
+                                let sound = match sound {
+                                    Some(sound) if !sound.too_long(max_duration) => sound,
 
-                                let (handler, _) = join_channel(&ctx, guild, user_channel).await;
+                                    _ => return Ok(()),
+                                };
 
-                                play_audio(
-                                    &mut sound,
-                                    volume,
+                                let guild_id = guild.id;
+                                let (handler, _) =
+                                    join_channel(&ctx, data, guild, user_channel).await;
+
+                                if let Err(e) = play_audio(
+                                    &sound,
+                                    new.user_id,
+                                    &guild_data,
                                     &mut handler.lock().await,
-                                    &data.database,
-                                    false,
+                                    data,
+                                    None,
+                                    Segment::default(),
+                                    &Effects::default(),
                                 )
                                 .await
-                                .unwrap();
+                                {
+                                    warn!(
+                                        "Failed to play greet sound {} in guild {}: {}",
+                                        sound.id, guild_id, e
+                                    );
+                                }
                             }
                         }
                     }
@@ -134,26 +145,39 @@ SELECT name, id, public, server_id, uploader_id
         }
         poise::Event::InteractionCreate { interaction } => match interaction {
             Interaction::MessageComponent(component) => {
-                if let Some(guild_id) = component.guild_id {
+                if let Some(guild) = component.guild_id.and_then(|g| g.to_guild_cached(&ctx)) {
                     if let Ok(()) = SoundPager::handle_interaction(ctx, &data, component).await {
-                    } else {
+                    } else if let Ok(()) = QueuePager::handle_interaction(ctx, component).await {
+                    } else if let Ok(()) =
+                        PanelButton::handle_interaction(ctx, &data, component).await
+                    {
+                    } else if let Some(member) = &component.member {
                         component
                             .create_interaction_response(ctx, |r| {
                                 r.kind(InteractionResponseType::DeferredUpdateMessage)
                             })
-                            .await
-                            .unwrap();
+                            .await?;
 
-                        play_from_query(
+                        let played = play_from_query(
                             &ctx,
                             &data,
-                            guild_id.to_guild_cached(&ctx).unwrap(),
-                            component.user.id,
+                            guild,
+                            member,
                             None,
                             &component.data.custom_id,
-                            false,
+                            None,
+                            Segment::default(),
+                            &Effects::default(),
                         )
                         .await;
+
+                        if let Err(e) = played {
+                            component
+                                .create_followup_message(ctx, |m| {
+                                    m.ephemeral(true).content(error::user_message(&e))
+                                })
+                                .await?;
+                        }
                     }
                 }
             }
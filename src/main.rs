@@ -1,16 +1,20 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod audio;
 mod cmds;
 mod consts;
 mod error;
 mod event_handlers;
 mod models;
+mod scheduler;
+mod storage;
 mod utils;
 
-use std::{env, path::Path, sync::Arc};
+use std::{collections::VecDeque, env, path::Path, sync::Arc, time::Instant};
 
 use dashmap::DashMap;
+use log::warn;
 use poise::serenity_prelude::{
     builder::CreateApplicationCommands,
     model::{
@@ -18,19 +22,37 @@ use poise::serenity_prelude::{
         id::{GuildId, UserId},
     },
 };
-use songbird::SerenityInit;
+use songbird::{tracks::TrackHandle, SerenityInit, Songbird};
 use sqlx::{MySql, Pool};
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, task::JoinHandle};
 
-use crate::{event_handlers::listener, models::guild_data::GuildData};
+use crate::{
+    cmds::now_playing::NowPlayingPanel,
+    consts::{CACHE_MAX_SIZE, CACHING_LOCATION, MEMORY_CACHE_ADMIT_PLAYS, MEMORY_CACHE_MAX_SIZE},
+    event_handlers::listener,
+    models::{guild_data::GuildData, sound::Sound},
+    storage::{MemoryCache, SoundCache, SoundStore},
+};
 
 type Database = MySql;
 
+// cheap to clone, so that background tasks can hold their own handle
+#[derive(Clone)]
 pub struct Data {
     database: Pool<Database>,
     http: reqwest::Client,
-    guild_data_cache: DashMap<GuildId, Arc<RwLock<GuildData>>>,
-    join_sound_cache: DashMap<UserId, DashMap<Option<GuildId>, Option<u32>>>,
+    sound_store: Arc<dyn SoundStore>,
+    sound_cache: Arc<SoundCache>,
+    memory_cache: Arc<MemoryCache>,
+    guild_data_cache: Arc<DashMap<GuildId, Arc<RwLock<GuildData>>>>,
+    join_sound_cache: Arc<DashMap<UserId, DashMap<Option<GuildId>, Option<u32>>>>,
+    active_tracks: Arc<DashMap<GuildId, Vec<TrackHandle>>>,
+    looping_tracks: Arc<DashMap<GuildId, Vec<TrackHandle>>>,
+    now_playing: Arc<DashMap<GuildId, NowPlayingPanel>>,
+    songbird: Arc<Songbird>,
+    idle_timers: Arc<DashMap<GuildId, JoinHandle<()>>>,
+    /// Recent play times of each user, for rate limiting
+    recent_plays: Arc<DashMap<(GuildId, UserId), VecDeque<Instant>>>,
 }
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -80,13 +102,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         commands: vec![
             cmds::info::help(),
             cmds::info::info(),
+            cmds::info::sound_details(),
             cmds::manage::change_public(),
+            cmds::manage::change_gain(),
+            poise::Command {
+                subcommands: vec![cmds::manage::add_tag(), cmds::manage::remove_tag()],
+                ..cmds::manage::tag()
+            },
+            poise::Command {
+                subcommands: vec![cmds::manage::add_alias(), cmds::manage::remove_alias()],
+                ..cmds::manage::alias()
+            },
             cmds::manage::upload_new_sound(),
+            cmds::manage::replace_sound(),
+            cmds::manage::bulk_upload(),
             cmds::manage::download_file(),
             cmds::manage::delete_sound(),
+            cmds::admin::renormalise_sounds(),
+            cmds::admin::cache_stats(),
             cmds::play::play(),
-            cmds::play::queue_play(),
+            poise::Command {
+                subcommands: vec![
+                    cmds::play::queue_play(),
+                    cmds::queue::view_queue(),
+                    cmds::queue::skip_queue(),
+                    cmds::queue::remove_from_queue(),
+                    cmds::queue::shuffle_queue(),
+                    cmds::queue::move_in_queue(),
+                    cmds::queue::clear_queue(),
+                    cmds::queue::pause_queue(),
+                    cmds::queue::resume_queue(),
+                ],
+                ..cmds::queue::queue()
+            },
+            poise::Command {
+                subcommands: vec![
+                    cmds::playlist::create_playlist(),
+                    cmds::playlist::add_to_playlist(),
+                    cmds::playlist::remove_from_playlist(),
+                    cmds::playlist::move_in_playlist(),
+                    cmds::playlist::delete_playlist(),
+                    cmds::playlist::list_playlists(),
+                    cmds::playlist::play_playlist(),
+                ],
+                ..cmds::playlist::playlist()
+            },
+            poise::Command {
+                subcommands: vec![
+                    cmds::schedule::add_schedule(),
+                    cmds::schedule::list_schedules(),
+                    cmds::schedule::cancel_schedule(),
+                ],
+                ..cmds::schedule::schedule()
+            },
             cmds::play::loop_play(),
+            cmds::stop::end_loop(),
             cmds::play::soundboard(),
             poise::Command {
                 subcommands: vec![
@@ -100,6 +170,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             cmds::stop::stop_playing(),
             cmds::stop::disconnect(),
             cmds::settings::change_volume(),
+            cmds::settings::change_max_duration(),
+            cmds::settings::change_playback_mode(),
+            cmds::settings::change_idle_timeout(),
+            poise::Command {
+                subcommands: vec![
+                    cmds::settings::set_rate_limit(),
+                    cmds::settings::disable_rate_limit(),
+                    cmds::settings::exempt_role(),
+                    cmds::settings::unexempt_role(),
+                ],
+                ..cmds::settings::rate_limit()
+            },
             poise::Command {
                 subcommands: vec![
                     poise::Command {
@@ -124,6 +206,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             },
         ],
         allowed_mentions: None,
+        on_error: |error| Box::pin(error::on_error(error)),
         listener: |ctx, event, _framework, data| Box::pin(listener(ctx, event, data)),
         ..Default::default()
     };
@@ -134,6 +217,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     sqlx::migrate!().run(&database).await?;
 
+    // `soundfx-rs migrate-store <from> <to>` moves all sound audio between storage backends
+    let args = env::args().collect::<Vec<String>>();
+    if args.get(1).map(|a| a.as_str()) == Some("migrate-store") {
+        match (args.get(2), args.get(3)) {
+            (Some(from), Some(to)) => {
+                let from = storage::store_from_name(from, database.clone())?;
+                let to = storage::store_from_name(to, database.clone())?;
+
+                storage::upgrade_legacy_keys(from.as_ref(), &database).await?;
+                let moved = storage::migrate(from.as_ref(), to.as_ref(), &database).await?;
+                println!("Moved {} files", moved);
+            }
+
+            _ => {
+                println!("Usage: soundfx-rs migrate-store <from> <to>");
+            }
+        }
+
+        return Ok(());
+    }
+
+    let sound_store = storage::store_from_name(
+        &env::var("SOUND_STORE").unwrap_or_else(|_| "database".to_string()),
+        database.clone(),
+    )?;
+    storage::upgrade_legacy_keys(sound_store.as_ref(), &database).await?;
+
+    let sound_cache = Arc::new(SoundCache::new(&*CACHING_LOCATION, *CACHE_MAX_SIZE));
+    sound_cache.load().await?;
+
     poise::Framework::builder()
         .token(discord_token)
         .user_data_setup(move |ctx, _bot, framework| {
@@ -142,12 +255,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                     .await
                     .unwrap();
 
-                Ok(Data {
+                let data = Data {
                     http: reqwest::Client::new(),
                     database,
+                    sound_store,
+                    sound_cache,
+                    memory_cache: Arc::new(MemoryCache::new(
+                        *MEMORY_CACHE_MAX_SIZE,
+                        *MEMORY_CACHE_ADMIT_PLAYS,
+                    )),
                     guild_data_cache: Default::default(),
                     join_sound_cache: Default::default(),
-                })
+                    active_tracks: Default::default(),
+                    looping_tracks: Default::default(),
+                    now_playing: Default::default(),
+                    songbird: songbird::get(ctx).await.unwrap(),
+                    idle_timers: Default::default(),
+                    recent_plays: Default::default(),
+                };
+
+                let backfill_data = data.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Sound::backfill_metadata(&backfill_data).await {
+                        warn!("Failed to backfill sound metadata: {:?}", e);
+                    }
+                });
+
+                tokio::spawn(scheduler::run(ctx.clone(), data.clone()));
+                tokio::spawn(utils::sweep_recent_plays(data.clone()));
+
+                Ok(data)
             })
         })
         .options(options)
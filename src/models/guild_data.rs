@@ -14,6 +14,20 @@ pub enum AllowGreet {
     Disabled = -1,
 }
 
+/// How a newly played sound interacts with sounds that are already playing
+#[derive(Copy, Clone, Type, PartialEq, poise::ChoiceParameter)]
+#[repr(i32)]
+pub enum PlaybackMode {
+    #[name = "Overlap"]
+    Overlap = 0,
+    #[name = "Interrupt"]
+    Interrupt = 1,
+    #[name = "Queue"]
+    Queue = 2,
+    #[name = "Overlap with limit"]
+    Limited = 3,
+}
+
 #[derive(Clone)]
 pub struct GuildData {
     pub id: u64,
@@ -21,6 +35,17 @@ pub struct GuildData {
     pub volume: u8,
     pub allow_greets: AllowGreet,
     pub allowed_role: Option<u64>,
+    pub max_play_duration: Option<u32>,
+    pub max_greet_duration: Option<u32>,
+    pub playback_mode: PlaybackMode,
+    /// Maximum number of overlapping sounds in `PlaybackMode::Limited`
+    pub overlap_limit: u8,
+    /// Seconds without playback before the bot leaves voice
+    pub idle_timeout: Option<u32>,
+    /// Plays allowed per user in each rate limit window. None disables rate limiting
+    pub rate_limit_plays: Option<u8>,
+    /// Length of the rate limit window in seconds
+    pub rate_limit_window: u32,
 }
 
 #[async_trait]
@@ -79,7 +104,8 @@ impl GuildData {
         let guild_data = sqlx::query_as_unchecked!(
             GuildData,
             "
-SELECT id, prefix, volume, allow_greets, allowed_role
+SELECT id, prefix, volume, allow_greets, allowed_role, max_play_duration, max_greet_duration,
+    playback_mode, overlap_limit, idle_timeout, rate_limit_plays, rate_limit_window
     FROM servers
     WHERE id = ?
             ",
@@ -119,6 +145,13 @@ INSERT INTO servers (id)
             volume: 100,
             allow_greets: AllowGreet::Enabled,
             allowed_role: None,
+            max_play_duration: None,
+            max_greet_duration: None,
+            playback_mode: PlaybackMode::Overlap,
+            overlap_limit: 3,
+            idle_timeout: None,
+            rate_limit_plays: None,
+            rate_limit_window: 10,
         })
     }
 
@@ -133,7 +166,14 @@ SET
     prefix = ?,
     volume = ?,
     allow_greets = ?,
-    allowed_role = ?
+    allowed_role = ?,
+    max_play_duration = ?,
+    max_greet_duration = ?,
+    playback_mode = ?,
+    overlap_limit = ?,
+    idle_timeout = ?,
+    rate_limit_plays = ?,
+    rate_limit_window = ?
 WHERE
     id = ?
             ",
@@ -141,11 +181,68 @@ WHERE
             self.volume,
             self.allow_greets,
             self.allowed_role,
+            self.max_play_duration,
+            self.max_greet_duration,
+            self.playback_mode,
+            self.overlap_limit,
+            self.idle_timeout,
+            self.rate_limit_plays,
+            self.rate_limit_window,
+            self.id
+        )
+        .execute(db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Roles whose members are never rate limited
+    pub async fn exempt_roles(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<u64>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT role_id FROM rate_limit_exempt_roles WHERE guild_id = ?",
             self.id
         )
+        .fetch_all(db_pool)
+        .await?
+        .into_iter()
+        .map(|row| row.role_id)
+        .collect())
+    }
+
+    pub async fn add_exempt_role(
+        &self,
+        role_id: u64,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT IGNORE INTO rate_limit_exempt_roles (guild_id, role_id) VALUES (?, ?)",
+            self.id,
+            role_id
+        )
         .execute(db_pool)
         .await?;
 
         Ok(())
     }
+
+    /// Returns false if the role wasn't exempt
+    pub async fn remove_exempt_role(
+        &self,
+        role_id: u64,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<bool, sqlx::Error> {
+        let removed = sqlx::query!(
+            "DELETE FROM rate_limit_exempt_roles WHERE guild_id = ? AND role_id = ?",
+            self.id,
+            role_id
+        )
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+
+        Ok(removed > 0)
+    }
 }
@@ -1,3 +1,5 @@
 pub mod guild_data;
 pub mod join_sound;
+pub mod playlist;
+pub mod schedule;
 pub mod sound;
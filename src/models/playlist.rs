@@ -0,0 +1,269 @@
+use poise::serenity_prelude::{GuildId, UserId};
+use sqlx::{Executor, Pool, Transaction};
+
+use crate::{models::sound::Sound, Database};
+
+/// Who a new playlist belongs to
+#[derive(Copy, Clone, PartialEq, poise::ChoiceParameter)]
+pub enum PlaylistOwner {
+    #[name = "Me"]
+    User,
+    #[name = "This server"]
+    Guild,
+}
+
+/// A named, ordered list of sounds. User playlists can be played in any server, and server
+/// playlists by anyone in the server
+pub struct Playlist {
+    pub id: u32,
+    pub name: String,
+    pub guild_id: Option<u64>,
+    pub user_id: Option<u64>,
+}
+
+impl Playlist {
+    /// Create a playlist, returning its ID, or None if the owner already has a playlist by that
+    /// name. Names are compared case-insensitively
+    pub async fn create(
+        name: &str,
+        owner: PlaylistOwner,
+        guild_id: GuildId,
+        user_id: UserId,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Option<u32>, sqlx::Error> {
+        let (guild_id, user_id) = match owner {
+            PlaylistOwner::User => (None, Some(user_id.0)),
+            PlaylistOwner::Guild => (Some(guild_id.0), None),
+        };
+
+        let result = sqlx::query!(
+            "
+INSERT IGNORE INTO playlists (name, guild_id, user_id)
+    VALUES (?, ?, ?)
+            ",
+            name,
+            guild_id,
+            user_id
+        )
+        .execute(db_pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(result.last_insert_id() as u32))
+        }
+    }
+
+    /// Find a playlist by name, preferring the user's own over the server's. Names are compared
+    /// case-insensitively, and are unique for each owner
+    pub async fn find(
+        name: &str,
+        guild_id: GuildId,
+        user_id: UserId,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Option<Playlist>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            Playlist,
+            "
+SELECT id, name, guild_id, user_id
+    FROM playlists
+    WHERE name = ? AND (
+        user_id = ? OR
+        guild_id = ?
+    )
+    ORDER BY user_id IS NULL
+    LIMIT 1
+            ",
+            name,
+            user_id.0,
+            guild_id.0
+        )
+        .fetch_optional(db_pool)
+        .await
+    }
+
+    /// Playlists the user can play in a guild: their own and the guild's
+    pub async fn available(
+        guild_id: GuildId,
+        user_id: UserId,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<Playlist>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            Playlist,
+            "
+SELECT id, name, guild_id, user_id
+    FROM playlists
+    WHERE user_id = ? OR guild_id = ?
+    ORDER BY user_id IS NULL, name
+            ",
+            user_id.0,
+            guild_id.0
+        )
+        .fetch_all(db_pool)
+        .await
+    }
+
+    pub async fn count_owned(
+        owner: PlaylistOwner,
+        guild_id: GuildId,
+        user_id: UserId,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<u64, sqlx::Error> {
+        let count = match owner {
+            PlaylistOwner::User => {
+                sqlx::query!(
+                    "SELECT COUNT(1) as count FROM playlists WHERE user_id = ?",
+                    user_id.0
+                )
+                .fetch_one(db_pool)
+                .await?
+                .count
+            }
+
+            PlaylistOwner::Guild => {
+                sqlx::query!(
+                "SELECT COUNT(1) as count FROM playlists WHERE guild_id = ? AND user_id IS NULL",
+                guild_id.0
+            )
+                .fetch_one(db_pool)
+                .await?
+                .count
+            }
+        };
+
+        Ok(count as u64)
+    }
+
+    /// Whether a user may change this playlist. Server playlists need Manage Server
+    pub fn editable_by(&self, user_id: UserId, manage_guild: bool) -> bool {
+        match self.user_id {
+            Some(owner) => owner == user_id.0,
+
+            None => manage_guild,
+        }
+    }
+
+    /// Every sound in the playlist, in order
+    pub async fn sounds(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<Sound>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            Sound,
+            "
+SELECT sounds.name, sounds.id, sounds.public, sounds.server_id, sounds.uploader_id,
+    sounds.duration, sounds.gain
+    FROM playlist_sounds
+    INNER JOIN sounds ON sounds.id = playlist_sounds.sound_id
+    WHERE playlist_sounds.playlist_id = ?
+    ORDER BY playlist_sounds.position
+            ",
+            self.id
+        )
+        .fetch_all(db_pool)
+        .await
+    }
+
+    /// Sounds in the playlist that the user can play in a guild, in order. Access is the same as
+    /// for searching: public sounds, the user's own sounds, and the guild's sounds
+    pub async fn playable_sounds(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<Sound>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            Sound,
+            "
+SELECT sounds.name, sounds.id, sounds.public, sounds.server_id, sounds.uploader_id,
+    sounds.duration, sounds.gain
+    FROM playlist_sounds
+    INNER JOIN sounds ON sounds.id = playlist_sounds.sound_id
+    WHERE playlist_sounds.playlist_id = ? AND (
+        sounds.public = 1 OR
+        sounds.uploader_id = ? OR
+        sounds.server_id = ?
+    )
+    ORDER BY playlist_sounds.position
+            ",
+            self.id,
+            user_id.0,
+            guild_id.0
+        )
+        .fetch_all(db_pool)
+        .await
+    }
+
+    /// Change the sounds in the playlist. The playlist is locked while `edit` runs, so that
+    /// concurrent edits apply one after the other. If `edit` returns a message for the user,
+    /// nothing is changed
+    pub async fn edit_sounds<T>(
+        &self,
+        db_pool: &Pool<Database>,
+        edit: impl FnOnce(&mut Vec<Sound>) -> Result<T, String>,
+    ) -> Result<Result<T, String>, sqlx::Error> {
+        let mut transaction = db_pool.begin().await?;
+
+        let exists = sqlx::query!("SELECT id FROM playlists WHERE id = ? FOR UPDATE", self.id)
+            .fetch_optional(&mut transaction)
+            .await?
+            .is_some();
+
+        if !exists {
+            return Ok(Err(format!("{} has been deleted", self.name)));
+        }
+
+        let mut sounds = self.sounds(&mut transaction).await?;
+
+        let edited = match edit(&mut sounds) {
+            Ok(edited) => edited,
+
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        let sound_ids = sounds.iter().map(|s| s.id).collect::<Vec<_>>();
+        self.write_sounds(&sound_ids, &mut transaction).await?;
+
+        transaction.commit().await?;
+
+        Ok(Ok(edited))
+    }
+
+    async fn write_sounds(
+        &self,
+        sound_ids: &[u32],
+        transaction: &mut Transaction<'_, Database>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM playlist_sounds WHERE playlist_id = ?", self.id)
+            .execute(&mut *transaction)
+            .await?;
+
+        for (position, sound_id) in sound_ids.iter().enumerate() {
+            sqlx::query!(
+                "
+INSERT INTO playlist_sounds (playlist_id, position, sound_id)
+    VALUES (?, ?, ?)
+                ",
+                self.id,
+                position as u32,
+                sound_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM playlists WHERE id = ?", self.id)
+            .execute(db_pool)
+            .await?;
+
+        Ok(())
+    }
+}
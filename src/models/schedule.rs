@@ -0,0 +1,345 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{Executor, Pool};
+
+use crate::Database;
+
+/// Sounds played in a voice channel at a set time, optionally repeating
+pub struct Schedule {
+    pub id: u32,
+    pub guild_id: u64,
+    pub channel_id: u64,
+    /// The user who created the schedule. Sounds are played with their access
+    pub user_id: u64,
+    /// Time of the next run, in UTC
+    pub next_run: NaiveDateTime,
+    /// Standard five-field cron expression for repeating schedules
+    pub recurrence: Option<String>,
+}
+
+pub struct ScheduledSound {
+    pub id: u32,
+    pub name: String,
+}
+
+/// The cron crate numbers weekdays from Sunday = 1, where standard cron uses Sunday = 0 or 7.
+/// Numeric days are rewritten as a list in the crate's numbering, and names are left alone
+fn convert_weekdays(field: &str) -> Result<String, String> {
+    let mut items = vec![];
+
+    for item in field.split(',') {
+        if !item.contains(|c: char| c.is_ascii_digit()) {
+            items.push(item.to_string());
+
+            continue;
+        }
+
+        let invalid = || format!("Invalid day of the week `{}`", item);
+        let day = |day: &str| match day.parse::<usize>() {
+            Ok(day) if day <= 7 => Ok(day),
+
+            _ => Err(invalid()),
+        };
+
+        let mut parts = item.splitn(2, '/');
+        let range = parts.next().unwrap_or_default();
+        let step = match parts.next() {
+            Some(step) => step
+                .parse::<usize>()
+                .ok()
+                .filter(|s| *s > 0)
+                .ok_or_else(invalid)?,
+
+            None => 1,
+        };
+
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+
+            None if range == "*" => (0, 6),
+
+            // a single day with a step runs from that day to the end of the week
+            None if step > 1 => (day(range)?, 6),
+
+            None => (day(range)?, day(range)?),
+        };
+
+        if first > last {
+            return Err(invalid());
+        }
+
+        for day in (first..=last).step_by(step) {
+            items.push((day % 7 + 1).to_string());
+        }
+    }
+
+    items.dedup();
+
+    Ok(items.join(","))
+}
+
+/// Parse a standard five-field cron expression, returning it tidied up for storage along with
+/// the schedule. Repeats are at most once a minute, as there is no seconds field
+pub fn parse_recurrence(expression: &str) -> Result<(String, cron::Schedule), String> {
+    let fields = expression.split_whitespace().collect::<Vec<_>>();
+
+    if fields.len() != 5 {
+        return Err(format!(
+            "Invalid repeat `{}`: use five fields (minute, hour, day of month, month and day of week)",
+            expression.trim()
+        ));
+    }
+
+    let weekdays = convert_weekdays(fields[4])?;
+    let crate_expression = format!(
+        "0 {} {} {} {} {}",
+        fields[0], fields[1], fields[2], fields[3], weekdays
+    );
+
+    match cron::Schedule::from_str(&crate_expression) {
+        Ok(schedule) => Ok((fields.join(" "), schedule)),
+
+        Err(e) => Err(format!("Invalid repeat `{}`: {}", fields.join(" "), e)),
+    }
+}
+
+impl Schedule {
+    pub async fn create(
+        guild_id: u64,
+        channel_id: u64,
+        user_id: u64,
+        next_run: NaiveDateTime,
+        recurrence: Option<&str>,
+        sound_ids: &[u32],
+        db_pool: &Pool<Database>,
+    ) -> Result<u32, sqlx::Error> {
+        let mut transaction = db_pool.begin().await?;
+
+        let schedule_id = sqlx::query!(
+            "
+INSERT INTO schedules (guild_id, channel_id, user_id, next_run, recurrence)
+    VALUES (?, ?, ?, ?, ?)
+            ",
+            guild_id,
+            channel_id,
+            user_id,
+            next_run,
+            recurrence
+        )
+        .execute(&mut transaction)
+        .await?
+        .last_insert_id() as u32;
+
+        for (position, sound_id) in sound_ids.iter().enumerate() {
+            sqlx::query!(
+                "
+INSERT INTO schedule_sounds (schedule_id, position, sound_id)
+    VALUES (?, ?, ?)
+                ",
+                schedule_id,
+                position as u32,
+                sound_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(schedule_id)
+    }
+
+    pub async fn guild_schedules(
+        guild_id: u64,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<Schedule>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            Schedule,
+            "
+SELECT id, guild_id, channel_id, user_id, next_run, recurrence
+    FROM schedules
+    WHERE guild_id = ?
+    ORDER BY next_run
+            ",
+            guild_id
+        )
+        .fetch_all(db_pool)
+        .await
+    }
+
+    /// Schedules whose next run is at or before `now`
+    pub async fn due(
+        now: NaiveDateTime,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<Schedule>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            Schedule,
+            "
+SELECT id, guild_id, channel_id, user_id, next_run, recurrence
+    FROM schedules
+    WHERE next_run <= ?
+            ",
+            now
+        )
+        .fetch_all(db_pool)
+        .await
+    }
+
+    pub async fn sounds(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<ScheduledSound>, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            ScheduledSound,
+            "
+SELECT sounds.id, sounds.name
+    FROM schedule_sounds
+    INNER JOIN sounds ON sounds.id = schedule_sounds.sound_id
+    WHERE schedule_sounds.schedule_id = ?
+    ORDER BY schedule_sounds.position
+            ",
+            self.id
+        )
+        .fetch_all(db_pool)
+        .await
+    }
+
+    /// The first run of a repeating schedule after `after`, or None if the schedule doesn't
+    /// repeat or never runs again
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let (_, recurrence) = parse_recurrence(self.recurrence.as_deref()?).ok()?;
+
+        recurrence
+            .after(&DateTime::<Utc>::from_utc(after, Utc))
+            .next()
+            .map(|next| next.naive_utc())
+    }
+
+    pub async fn reschedule(
+        &mut self,
+        next_run: NaiveDateTime,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<(), sqlx::Error> {
+        self.next_run = next_run;
+
+        sqlx::query!(
+            "
+UPDATE schedules
+SET
+    next_run = ?
+WHERE
+    id = ?
+            ",
+            self.next_run,
+            self.id
+        )
+        .execute(db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM schedules WHERE id = ?", self.id)
+            .execute(db_pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn runs_after(expression: &str, after: &str, count: usize) -> Vec<NaiveDateTime> {
+        let (_, schedule) = parse_recurrence(expression).unwrap();
+
+        schedule
+            .after(&DateTime::<Utc>::from_utc(time(after), Utc))
+            .take(count)
+            .map(|run| run.naive_utc())
+            .collect()
+    }
+
+    #[test]
+    fn recurrence_stored_tidied() {
+        let (expression, _) = parse_recurrence(" 0  * * * * ").unwrap();
+
+        assert_eq!(expression, "0 * * * *");
+    }
+
+    #[test]
+    fn recurrence_runs_when_expected() {
+        assert_eq!(
+            runs_after("15 9 * * *", "2026-01-01 10:00", 1),
+            vec![time("2026-01-02 09:15")]
+        );
+    }
+
+    #[test]
+    fn recurrence_weekday_range() {
+        // 2026-01-02 is a Friday
+        assert_eq!(
+            runs_after("0 9 * * 1-5", "2026-01-02 10:00", 2),
+            vec![time("2026-01-05 09:00"), time("2026-01-06 09:00")]
+        );
+        assert_eq!(
+            runs_after("0 9 * * MON-FRI", "2026-01-02 10:00", 1),
+            vec![time("2026-01-05 09:00")]
+        );
+    }
+
+    #[test]
+    fn recurrence_sunday() {
+        assert_eq!(
+            runs_after("0 9 * * 0", "2026-01-01 10:00", 1),
+            vec![time("2026-01-04 09:00")]
+        );
+        assert_eq!(
+            runs_after("0 9 * * 7", "2026-01-01 10:00", 1),
+            vec![time("2026-01-04 09:00")]
+        );
+        assert_eq!(
+            runs_after("0 9 * * 5-7", "2026-01-01 10:00", 3),
+            vec![
+                time("2026-01-02 09:00"),
+                time("2026-01-03 09:00"),
+                time("2026-01-04 09:00")
+            ]
+        );
+    }
+
+    #[test]
+    fn weekdays_converted() {
+        assert_eq!(convert_weekdays("*").unwrap(), "*");
+        assert_eq!(convert_weekdays("1-5").unwrap(), "2,3,4,5,6");
+        assert_eq!(convert_weekdays("0,6").unwrap(), "1,7");
+        assert_eq!(convert_weekdays("*/2").unwrap(), "1,3,5,7");
+        assert_eq!(convert_weekdays("Sun,Sat").unwrap(), "Sun,Sat");
+        assert!(convert_weekdays("8").is_err());
+        assert!(convert_weekdays("5-1").is_err());
+        assert!(convert_weekdays("1/0").is_err());
+    }
+
+    #[test]
+    fn recurrence_invalid() {
+        assert!(parse_recurrence("every hour").is_err());
+        assert!(parse_recurrence("61 * * * *").is_err());
+        assert!(parse_recurrence("").is_err());
+    }
+
+    #[test]
+    fn recurrence_seconds_rejected() {
+        assert!(parse_recurrence("* * * * * *").is_err());
+        assert!(parse_recurrence("30 0 12 * * Mon").is_err());
+    }
+}
@@ -1,11 +1,20 @@
-use std::{env, path::Path};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
+use log::{info, warn};
 use poise::serenity_prelude::async_trait;
-use songbird::input::restartable::Restartable;
+use songbird::input::{cached::Compressed, restartable::Restartable, Bitrate, Input};
 use sqlx::Executor;
-use tokio::{fs::File, io::AsyncWriteExt, process::Command};
+use tokio::fs;
 
-use crate::{consts::UPLOAD_MAX_SIZE, error::ErrorTypes, Data, Database};
+use crate::{
+    audio::{self, DownloadFormat, Effects, Trim},
+    consts::{UPLOAD_MAX_DURATION, UPLOAD_MAX_SIZE},
+    error::ErrorTypes,
+    storage, Data, Database, Error,
+};
 
 #[derive(Clone)]
 pub struct Sound {
@@ -14,6 +23,23 @@ pub struct Sound {
     pub public: bool,
     pub server_id: u64,
     pub uploader_id: Option<u64>,
+    pub duration: Option<f64>,
+    /// Multiplier applied on top of the guild volume
+    pub gain: f32,
+}
+
+pub struct SoundMetadata {
+    pub duration: Option<f64>,
+    pub channels: Option<u8>,
+    pub sample_rate: Option<u32>,
+    pub bitrate: Option<u32>,
+}
+
+pub struct UploadedSound {
+    pub id: u32,
+    pub version: u32,
+    pub duration: Option<f64>,
+    pub size: usize,
 }
 
 impl PartialEq for Sound {
@@ -22,6 +48,23 @@ impl PartialEq for Sound {
     }
 }
 
+/// Lowercase a tag, or None if it is empty, too long or has characters other than letters,
+/// digits, `-` and `_`
+pub fn normalise_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty()
+        || tag.chars().count() > 20
+        || !tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
 #[async_trait]
 pub trait SoundCtx {
     async fn search_for_sound<G: Into<u64> + Send, U: Into<u64> + Send>(
@@ -31,6 +74,13 @@ pub trait SoundCtx {
         user_id: U,
         strict: bool,
     ) -> Result<Vec<Sound>, sqlx::Error>;
+    async fn search_tagged_sounds<G: Into<u64> + Send, U: Into<u64> + Send>(
+        &self,
+        query: &str,
+        tags: &[String],
+        guild_id: G,
+        user_id: U,
+    ) -> Result<Vec<Sound>, sqlx::Error>;
     async fn autocomplete_user_sounds<U: Into<u64> + Send, G: Into<u64> + Send>(
         &self,
         query: &str,
@@ -85,7 +135,7 @@ impl SoundCtx for Data {
             let sound = sqlx::query_as_unchecked!(
                 Sound,
                 "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE id = ? AND (
         public = 1 OR
@@ -106,23 +156,35 @@ SELECT name, id, public, server_id, uploader_id
             let sound;
 
             if strict {
+                // names and aliases are looked up separately, so that each uses its own index
                 sound = sqlx::query_as_unchecked!(
                     Sound,
                     "
-SELECT name, id, public, server_id, uploader_id
-    FROM sounds
-    WHERE name = ? AND (
+SELECT name, id, public, server_id, uploader_id, duration, gain
+    FROM (
+        SELECT name, id, public, server_id, uploader_id, duration, gain
+            FROM sounds
+            WHERE name = ?
+        UNION
+        SELECT sounds.name, sounds.id, sounds.public, sounds.server_id, sounds.uploader_id,
+            sounds.duration, sounds.gain
+            FROM sound_aliases
+            INNER JOIN sounds ON sounds.id = sound_aliases.sound_id
+            WHERE sound_aliases.alias = ?
+    ) AS matches
+    WHERE
         public = 1 OR
         uploader_id = ? OR
         server_id = ?
-    )
-    ORDER BY uploader_id = ? DESC, server_id = ? DESC, public = 1 DESC, rand()
+    ORDER BY uploader_id = ? DESC, server_id = ? DESC, public = 1 DESC, name = ? DESC, rand()
                     ",
                     name,
+                    name,
                     user_id,
                     guild_id,
                     user_id,
-                    guild_id
+                    guild_id,
+                    name
                 )
                 .fetch_all(&db_pool)
                 .await?;
@@ -130,9 +192,13 @@ SELECT name, id, public, server_id, uploader_id
                 sound = sqlx::query_as_unchecked!(
                     Sound,
                     "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
-    WHERE name LIKE CONCAT('%', ?, '%') AND (
+    WHERE (
+        name LIKE CONCAT('%', ?, '%') OR
+        id IN (SELECT sound_id FROM sound_aliases WHERE alias LIKE CONCAT('%', ?, '%')) OR
+        id IN (SELECT sound_id FROM sound_tags WHERE tag = ?)
+    ) AND (
         public = 1 OR
         uploader_id = ? OR
         server_id = ?
@@ -140,6 +206,8 @@ SELECT name, id, public, server_id, uploader_id
     ORDER BY uploader_id = ? DESC, server_id = ? DESC, public = 1 DESC, rand()
                     ",
                     name,
+                    name,
+                    name,
                     user_id,
                     guild_id,
                     user_id,
@@ -153,6 +221,58 @@ SELECT name, id, public, server_id, uploader_id
         }
     }
 
+    /// Fuzzy search limited to sounds that have every one of `tags`. Tags never contain commas
+    async fn search_tagged_sounds<G: Into<u64> + Send, U: Into<u64> + Send>(
+        &self,
+        query: &str,
+        tags: &[String],
+        guild_id: G,
+        user_id: U,
+    ) -> Result<Vec<Sound>, sqlx::Error> {
+        let guild_id = guild_id.into();
+        let user_id = user_id.into();
+
+        sqlx::query_as_unchecked!(
+            Sound,
+            "
+SELECT name, id, public, server_id, uploader_id, duration, gain
+    FROM (
+        SELECT name, id, public, server_id, uploader_id, duration, gain
+            FROM sounds
+            WHERE name LIKE CONCAT('%', ?, '%')
+        UNION
+        SELECT sounds.name, sounds.id, sounds.public, sounds.server_id, sounds.uploader_id,
+            sounds.duration, sounds.gain
+            FROM sound_aliases
+            INNER JOIN sounds ON sounds.id = sound_aliases.sound_id
+            WHERE sound_aliases.alias LIKE CONCAT('%', ?, '%')
+    ) AS matches
+    WHERE id IN (
+        SELECT sound_id
+            FROM sound_tags
+            WHERE FIND_IN_SET(tag, ?)
+            GROUP BY sound_id
+            HAVING COUNT(1) = ?
+    ) AND (
+        public = 1 OR
+        uploader_id = ? OR
+        server_id = ?
+    )
+    ORDER BY uploader_id = ? DESC, server_id = ? DESC, public = 1 DESC, rand()
+            ",
+            query,
+            query,
+            tags.join(","),
+            tags.len() as u32,
+            user_id,
+            guild_id,
+            user_id,
+            guild_id
+        )
+        .fetch_all(&self.database)
+        .await
+    }
+
     async fn autocomplete_user_sounds<U: Into<u64> + Send, G: Into<u64> + Send>(
         &self,
         query: &str,
@@ -164,12 +284,16 @@ SELECT name, id, public, server_id, uploader_id
         sqlx::query_as_unchecked!(
             Sound,
             "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
 FROM sounds
-WHERE name LIKE CONCAT(?, '%') AND (uploader_id = ? OR server_id = ?)
+WHERE (
+    name LIKE CONCAT(?, '%') OR
+    id IN (SELECT sound_id FROM sound_aliases WHERE alias LIKE CONCAT(?, '%'))
+) AND (uploader_id = ? OR server_id = ?)
 LIMIT 25
             ",
             query,
+            query,
             user_id.into(),
             guild_id.into(),
         )
@@ -187,7 +311,7 @@ LIMIT 25
                 sqlx::query_as_unchecked!(
                     Sound,
                     "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE uploader_id = ?
     ORDER BY id DESC
@@ -204,7 +328,7 @@ SELECT name, id, public, server_id, uploader_id
                 sqlx::query_as_unchecked!(
                     Sound,
                     "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE uploader_id = ?
     ORDER BY id DESC
@@ -229,7 +353,7 @@ SELECT name, id, public, server_id, uploader_id
                 sqlx::query_as_unchecked!(
                     Sound,
                     "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE server_id = ?
     ORDER BY id DESC
@@ -247,7 +371,7 @@ SELECT name, id, public, server_id, uploader_id
                 sqlx::query_as_unchecked!(
                     Sound,
                     "
-SELECT name, id, public, server_id, uploader_id
+SELECT name, id, public, server_id, uploader_id, duration, gain
     FROM sounds
     WHERE server_id = ?
     ORDER BY id DESC
@@ -287,55 +411,187 @@ SELECT name, id, public, server_id, uploader_id
 }
 
 impl Sound {
-    async fn src(&self, db_pool: impl Executor<'_, Database = Database>) -> Vec<u8> {
-        struct Src {
-            src: Vec<u8>,
-        }
+    async fn source_hash(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        Ok(
+            sqlx::query!("SELECT source_hash FROM sounds WHERE id = ?", self.id)
+                .fetch_optional(db_pool)
+                .await?
+                .and_then(|r| r.source_hash),
+        )
+    }
 
-        let record = sqlx::query_as_unchecked!(
-            Src,
-            "
-SELECT src
-    FROM sounds
-    WHERE id = ?
-    LIMIT 1
-            ",
+    async fn src(&self, data: &Data) -> Result<Vec<u8>, Error> {
+        let hash = self
+            .source_hash(&data.database)
+            .await?
+            .ok_or(ErrorTypes::MissingSource)?;
+
+        data.sound_store
+            .get(&hash)
+            .await?
+            .ok_or_else(|| Box::new(ErrorTypes::MissingSource) as Error)
+    }
+
+    /// Point this sound at new audio, releasing the audio it previously referenced
+    async fn set_src(&self, data: &Data, src: &[u8]) -> Result<(), Error> {
+        let old_hash = self.source_hash(&data.database).await?;
+        let new_hash = storage::acquire(data.sound_store.as_ref(), &data.database, src).await?;
+
+        sqlx::query!(
+            "UPDATE sounds SET source_hash = ? WHERE id = ?",
+            new_hash,
             self.id
         )
-        .fetch_one(db_pool)
-        .await
-        .unwrap();
+        .execute(&data.database)
+        .await?;
+
+        if let Some(old_hash) = old_hash {
+            storage::release(data.sound_store.as_ref(), &data.database, &old_hash).await?;
+        }
+
+        data.sound_cache.invalidate_sound(self.id).await;
+        data.memory_cache.invalidate_sound(self.id);
+
+        Ok(())
+    }
+
+    pub async fn store_sound_source(&self, data: &Data) -> Result<PathBuf, Error> {
+        match data.sound_cache.get(self.id, None).await {
+            Some(path) => Ok(path),
+
+            None => {
+                data.sound_cache
+                    .insert(self.id, None, &self.src(data).await?)
+                    .await
+            }
+        }
+    }
+
+    /// Reuses a cached copy of the variant if available
+    async fn store_variant(
+        &self,
+        data: &Data,
+        variant: &str,
+        filter: Option<&str>,
+        output_args: &[&str],
+    ) -> Result<PathBuf, Error> {
+        if let Some(path) = data.sound_cache.get(self.id, Some(variant)).await {
+            return Ok(path);
+        }
 
-        record.src
+        let source = self.store_sound_source(data).await?;
+        let processed =
+            audio::transcode(&source.to_string_lossy(), filter, output_args, None).await?;
+
+        data.sound_cache
+            .insert(self.id, Some(variant), &processed)
+            .await
     }
 
-    pub async fn store_sound_source(
+    pub async fn store_processed_source(
         &self,
-        db_pool: impl Executor<'_, Database = Database>,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let caching_location = env::var("CACHING_LOCATION").unwrap_or(String::from("/tmp"));
+        data: &Data,
+        effects: &Effects,
+    ) -> Result<PathBuf, Error> {
+        match (effects.filter(), effects.hash()) {
+            (Some(filter), Some(hash)) => {
+                self.store_variant(
+                    data,
+                    &format!("fx-{}", hash),
+                    Some(&filter),
+                    &["-ar", "48000", "-f", "opus"],
+                )
+                .await
+            }
 
-        let path_name = format!("{}/sound-{}", caching_location, self.id);
-        let path = Path::new(&path_name);
+            _ => self.store_sound_source(data).await,
+        }
+    }
 
-        if !path.exists() {
-            let mut file = File::create(&path).await?;
+    pub async fn store_download_source(
+        &self,
+        data: &Data,
+        format: &DownloadFormat,
+    ) -> Result<PathBuf, Error> {
+        match format.output_args() {
+            Some(output_args) => {
+                self.store_variant(
+                    data,
+                    &format!("dl-{}", format.extension()),
+                    None,
+                    output_args,
+                )
+                .await
+            }
 
-            file.write_all(&self.src(db_pool).await).await?;
+            None => self.store_sound_source(data).await,
         }
+    }
+
+    /// If the cached audio can't be read, it is discarded and fetched from the store again once
+    pub async fn playable(&self, data: &Data, effects: &Effects) -> Result<Input, Error> {
+        match self.load_playable(data, effects).await {
+            Ok(input) => Ok(input),
+
+            Err(e) => match e.downcast_ref::<ErrorTypes>() {
+                // re-fetching can't help if the store has nothing
+                Some(ErrorTypes::MissingSource) | Some(ErrorTypes::Database(_)) => Err(e),
 
-        Ok(path_name)
+                _ => {
+                    warn!(
+                        "Failed to load sound {} for playback. Re-fetching: {}",
+                        self.id, e
+                    );
+
+                    data.sound_cache.invalidate_sound(self.id).await;
+                    data.memory_cache.invalidate_sound(self.id);
+
+                    self.load_playable(data, effects).await
+                }
+            },
+        }
     }
 
-    pub async fn playable(
-        &self,
-        db_pool: impl Executor<'_, Database = Database>,
-    ) -> Result<Restartable, Box<dyn std::error::Error + Send + Sync>> {
-        let path_name = self.store_sound_source(db_pool).await?;
+    async fn load_playable(&self, data: &Data, effects: &Effects) -> Result<Input, Error> {
+        let variant = effects.hash().map(|hash| format!("fx-{}", hash));
 
-        Ok(Restartable::ffmpeg(path_name, false)
-            .await
-            .expect("FFMPEG ERROR!"))
+        if let Some(input) = data.memory_cache.get(self.id, variant.as_deref()) {
+            return Ok(input);
+        }
+
+        let path_name = self.store_processed_source(data, effects).await?;
+
+        // the encoded file is roughly the same size as the compressed copy held in memory
+        let size = fs::metadata(&path_name).await?.len();
+        if data
+            .memory_cache
+            .should_admit(self.id, variant.as_deref(), size)
+        {
+            let source = songbird::ffmpeg(&path_name).await?;
+            let compressed = tokio::task::spawn_blocking(move || {
+                Compressed::new(source, Bitrate::BitsPerSecond(96_000))
+            })
+            .await??;
+
+            let input = compressed.new_handle().into();
+            data.memory_cache
+                .insert(self.id, variant.as_deref(), compressed, size);
+
+            return Ok(input);
+        }
+
+        let restartable =
+            Restartable::ffmpeg(path_name, false)
+                .await
+                .map_err(|e| ErrorTypes::FfmpegFailed {
+                    status: None,
+                    stderr: e.to_string(),
+                })?;
+
+        Ok(restartable.into())
     }
 
     pub async fn count_user_sounds<U: Into<u64>>(
@@ -392,11 +648,13 @@ SELECT COUNT(1) as count
             "
 UPDATE sounds
 SET
-    public = ?
+    public = ?,
+    gain = ?
 WHERE
     id = ?
             ",
             self.public,
+            self.gain,
             self.id
         )
         .execute(db_pool)
@@ -405,79 +663,453 @@ WHERE
         Ok(())
     }
 
-    pub async fn delete(
+    pub async fn tags(
         &self,
         db_pool: impl Executor<'_, Database = Database>,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<Vec<String>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT tag FROM sound_tags WHERE sound_id = ? ORDER BY tag",
+            self.id
+        )
+        .fetch_all(db_pool)
+        .await?
+        .into_iter()
+        .map(|row| row.tag)
+        .collect())
+    }
+
+    pub async fn aliases(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT alias FROM sound_aliases WHERE sound_id = ? ORDER BY alias",
+            self.id
+        )
+        .fetch_all(db_pool)
+        .await?
+        .into_iter()
+        .map(|row| row.alias)
+        .collect())
+    }
+
+    pub async fn add_tag(
+        &self,
+        tag: &str,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT IGNORE INTO sound_tags (sound_id, tag) VALUES (?, ?)",
+            self.id,
+            tag
+        )
+        .execute(db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns whether the sound had the tag
+    pub async fn remove_tag(
+        &self,
+        tag: &str,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM sound_tags WHERE sound_id = ? AND tag = ?",
+            self.id,
+            tag
+        )
+        .execute(db_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn add_alias(
+        &self,
+        alias: &str,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT IGNORE INTO sound_aliases (sound_id, alias) VALUES (?, ?)",
+            self.id,
+            alias
+        )
+        .execute(db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns whether the sound had the alias
+    pub async fn remove_alias(
+        &self,
+        alias: &str,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM sound_aliases WHERE sound_id = ? AND alias = ?",
+            self.id,
+            alias
+        )
+        .execute(db_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete(&self, data: &Data) -> Result<(), Error> {
+        let hash = self.source_hash(&data.database).await?;
+
         sqlx::query!("DELETE FROM sounds WHERE id = ?", self.id)
-            .execute(db_pool)
+            .execute(&data.database)
             .await?;
 
+        // other sounds may share the same audio, so it is only removed once unreferenced
+        if let Some(hash) = hash {
+            storage::release(data.sound_store.as_ref(), &data.database, &hash).await?;
+        }
+        data.sound_cache.invalidate_sound(self.id).await;
+        data.memory_cache.invalidate_sound(self.id);
+
+        Ok(())
+    }
+
+    pub fn too_long(&self, max_duration: Option<u32>) -> bool {
+        match (self.duration, max_duration) {
+            (Some(duration), Some(max_duration)) => duration > max_duration as f64,
+
+            _ => false,
+        }
+    }
+
+    pub async fn metadata(
+        &self,
+        db_pool: impl Executor<'_, Database = Database>,
+    ) -> Result<SoundMetadata, sqlx::Error> {
+        sqlx::query_as_unchecked!(
+            SoundMetadata,
+            "
+SELECT duration, channels, sample_rate, bitrate
+    FROM sounds
+    WHERE id = ?
+            ",
+            self.id
+        )
+        .fetch_one(db_pool)
+        .await
+    }
+
+    pub async fn probe_metadata(&self, data: &Data) -> Result<audio::Metadata, Error> {
+        let path = self.store_sound_source(data).await?;
+
+        self.record_metadata(data, &path).await
+    }
+
+    async fn record_metadata(&self, data: &Data, path: &Path) -> Result<audio::Metadata, Error> {
+        let metadata = audio::probe(&path.to_string_lossy()).await?;
+
+        sqlx::query!(
+            "
+UPDATE sounds
+SET
+    duration = ?,
+    channels = ?,
+    sample_rate = ?,
+    bitrate = ?,
+    metadata_checked = 1
+WHERE
+    id = ?
+            ",
+            metadata.duration,
+            metadata.channels,
+            metadata.sample_rate,
+            metadata.bitrate,
+            self.id
+        )
+        .execute(&data.database)
+        .await?;
+
+        Ok(metadata)
+    }
+
+    /// Probe metadata for all sounds uploaded before metadata was recorded. Audio is read straight
+    /// from the store, so that old sounds don't push the sounds being played out of the cache
+    pub async fn backfill_metadata(data: &Data) -> Result<(), Error> {
+        let sounds = sqlx::query_as_unchecked!(
+            Sound,
+            "
+SELECT name, id, public, server_id, uploader_id, duration, gain
+    FROM sounds
+    WHERE metadata_checked = 0
+            "
+        )
+        .fetch_all(&data.database)
+        .await?;
+
+        if sounds.is_empty() {
+            return Ok(());
+        }
+
+        info!("Backfilling metadata for {} sounds", sounds.len());
+
+        let mut failed = 0;
+        for sound in &sounds {
+            let path = env::temp_dir().join(format!("soundfx-probe-{}", sound.id));
+
+            let probed = match sound.src(data).await {
+                Ok(src) => match fs::write(&path, &src).await {
+                    Ok(()) => sound.record_metadata(data, &path).await.map(|_| ()),
+
+                    Err(e) => Err(e.into()),
+                },
+
+                Err(e) => Err(e),
+            };
+            let _ = fs::remove_file(&path).await;
+
+            if let Err(e) = probed {
+                warn!("Failed to probe sound {}: {:?}", sound.id, e);
+
+                // not retried, as a sound that can't be probed now won't be on the next start
+                sqlx::query!(
+                    "UPDATE sounds SET metadata_checked = 1 WHERE id = ?",
+                    sound.id
+                )
+                .execute(&data.database)
+                .await?;
+
+                failed += 1;
+            }
+        }
+
+        info!(
+            "Finished backfilling metadata for {} sounds ({} failed)",
+            sounds.len(),
+            failed
+        );
+
+        Ok(())
+    }
+
+    pub async fn normalise(&self, data: &Data) -> Result<(), Error> {
+        let path = self.store_sound_source(data).await?;
+
+        // the stored audio was already limited in size when uploaded
+        let (src, loudness) = audio::normalise_opus(&path.to_string_lossy(), None, None).await?;
+        let loudness = Some(loudness).filter(|l| l.is_measurable());
+
+        self.set_src(data, &src).await?;
+
+        sqlx::query!(
+            "
+UPDATE sounds
+SET
+    loudness = ?,
+    peak = ?,
+    normalised = 1
+WHERE
+    id = ?
+            ",
+            loudness.as_ref().map(|l| l.integrated),
+            loudness.as_ref().map(|l| l.true_peak),
+            self.id
+        )
+        .execute(&data.database)
+        .await?;
+
         Ok(())
     }
 
     pub async fn create_anon<G: Into<u64>, U: Into<u64>>(
         name: &str,
         src_url: &str,
+        trim: &Trim,
         server_id: G,
         user_id: U,
-        db_pool: impl Executor<'_, Database = Database>,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync + Send>> {
+        data: &Data,
+    ) -> Result<UploadedSound, Error> {
         let server_id = server_id.into();
         let user_id = user_id.into();
 
-        async fn process_src(src_url: &str) -> Option<Vec<u8>> {
-            let output = Command::new("ffmpeg")
-                .kill_on_drop(true)
-                .arg("-i")
-                .arg(src_url)
-                .arg("-loglevel")
-                .arg("error")
-                .arg("-f")
-                .arg("opus")
-                .arg("-fs")
-                .arg(UPLOAD_MAX_SIZE.to_string())
-                .arg("pipe:1")
-                .output()
-                .await;
-
-            match output {
-                Ok(out) => {
-                    if out.status.success() {
-                        Some(out.stdout)
-                    } else {
-                        None
-                    }
-                }
+        let (src, loudness, duration) = Self::process_upload(src_url, trim).await?;
+
+        let hash = storage::acquire(data.sound_store.as_ref(), &data.database, &src).await?;
+
+        let insert = sqlx::query!(
+            "
+INSERT INTO sounds (name, server_id, uploader_id, public, loudness, peak, normalised, source_hash)
+    VALUES (?, ?, ?, 1, ?, ?, 1, ?)
+            ",
+            name,
+            server_id,
+            user_id,
+            loudness.as_ref().map(|l| l.integrated),
+            loudness.as_ref().map(|l| l.true_peak),
+            hash
+        )
+        .execute(&data.database)
+        .await;
+
+        let sound_id = match insert {
+            Ok(result) => result.last_insert_id() as u32,
+
+            Err(e) => {
+                storage::release(data.sound_store.as_ref(), &data.database, &hash).await?;
 
-                Err(_) => None,
+                return Err(ErrorTypes::Database(e).into());
             }
-        }
+        };
 
-        let source = process_src(src_url).await;
+        let sound = Sound {
+            name: name.to_string(),
+            id: sound_id,
+            public: true,
+            server_id,
+            uploader_id: Some(user_id),
+            duration: None,
+            gain: 1.0,
+        };
 
-        match source {
-            Some(data) => {
-                match sqlx::query!(
-                    "
-INSERT INTO sounds (name, server_id, uploader_id, public, src)
-    VALUES (?, ?, ?, 1, ?)
-                ",
-                    name,
-                    server_id,
-                    user_id,
-                    data
+        Ok(UploadedSound {
+            id: sound_id,
+            version: 1,
+            duration: sound.probe_uploaded_duration(data, duration).await,
+            size: src.len(),
+        })
+    }
+
+    /// Replace the audio of this sound with a new upload, keeping its ID
+    pub async fn replace(
+        &self,
+        src_url: &str,
+        trim: &Trim,
+        data: &Data,
+    ) -> Result<UploadedSound, Error> {
+        let (src, loudness, duration) = Self::process_upload(src_url, trim).await?;
+
+        self.set_src(data, &src).await?;
+
+        sqlx::query!(
+            "
+UPDATE sounds
+SET
+    version = version + 1,
+    loudness = ?,
+    peak = ?,
+    normalised = 1
+WHERE
+    id = ?
+            ",
+            loudness.as_ref().map(|l| l.integrated),
+            loudness.as_ref().map(|l| l.true_peak),
+            self.id
+        )
+        .execute(&data.database)
+        .await?;
+
+        let version = sqlx::query!("SELECT version FROM sounds WHERE id = ?", self.id)
+            .fetch_one(&data.database)
+            .await?
+            .version;
+
+        Ok(UploadedSound {
+            id: self.id,
+            version,
+            duration: self.probe_uploaded_duration(data, duration).await,
+            size: src.len(),
+        })
+    }
+
+    /// Trim, normalise and encode an uploaded file, returning the encoded audio, its loudness
+    /// before normalisation and the expected duration, if known
+    async fn process_upload(
+        src_url: &str,
+        trim: &Trim,
+    ) -> Result<(Vec<u8>, Option<audio::Loudness>, Option<f64>), Error> {
+        let source_metadata = audio::probe(src_url).await?;
+
+        // trimming needs the length of the source, but untrimmed uploads don't
+        let (filter, duration) = match source_metadata.duration {
+            _ if trim.is_empty() => (None, source_metadata.duration),
+
+            Some(source_duration) => {
+                let (filter, duration) = trim
+                    .filter(source_duration)
+                    .map_err(ErrorTypes::InvalidTrim)?;
+
+                (filter, Some(duration))
+            }
+
+            None => {
+                return Err(ErrorTypes::InvalidTrim(
+                    "The length of the file couldn't be read, so it can't be trimmed. Try converting it to MP3 or OGG first.".to_string(),
                 )
-                .execute(db_pool)
-                .await
-                {
-                    Ok(_) => Ok(()),
+                .into());
+            }
+        };
 
-                    Err(e) => Err(Box::new(e)),
+        if let (Some(duration), Some(max_duration)) = (duration, *UPLOAD_MAX_DURATION) {
+            if duration > max_duration {
+                return Err(ErrorTypes::TooLong {
+                    duration,
+                    max_duration,
                 }
+                .into());
             }
+        }
 
-            None => Err(Box::new(ErrorTypes::InvalidFile)),
+        let (src, loudness) =
+            audio::normalise_opus(src_url, filter.as_deref(), Some(*UPLOAD_MAX_SIZE)).await?;
+
+        if loudness.is_silent() {
+            return Err(ErrorTypes::Silent.into());
         }
+
+        // clips too short to measure are kept at their original loudness
+        Ok((src, Some(loudness).filter(|l| l.is_measurable()), duration))
+    }
+
+    /// Record metadata for newly uploaded audio. The probed duration is exact, so it is preferred
+    /// over the duration expected from the trim
+    async fn probe_uploaded_duration(&self, data: &Data, expected: Option<f64>) -> Option<f64> {
+        match self.probe_metadata(data).await {
+            Ok(metadata) => metadata.duration.or(expected),
+
+            Err(e) => {
+                warn!("Failed to probe uploaded sound {}: {:?}", self.id, e);
+
+                expected
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_lowercased_and_trimmed() {
+        assert_eq!(normalise_tag("  Meme "), Some("meme".to_string()));
+        assert_eq!(normalise_tag("sound_fx-2"), Some("sound_fx-2".to_string()));
+        assert_eq!(normalise_tag("été"), Some("été".to_string()));
+    }
+
+    #[test]
+    fn tag_length() {
+        assert_eq!(normalise_tag(""), None);
+        assert_eq!(normalise_tag("   "), None);
+        assert_eq!(normalise_tag(&"a".repeat(20)), Some("a".repeat(20)));
+        assert_eq!(normalise_tag(&"a".repeat(21)), None);
+    }
+
+    #[test]
+    fn tag_characters() {
+        assert_eq!(normalise_tag("two words"), None);
+        assert_eq!(normalise_tag("a,b"), None);
+        assert_eq!(normalise_tag("tag:meme"), None);
+        assert_eq!(normalise_tag("%"), None);
     }
 }
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use log::{info, warn};
+use poise::serenity_prelude::{self, model::channel::Channel, ChannelId, GuildId, Member, UserId};
+
+use crate::{
+    audio::Effects,
+    models::{guild_data::CtxGuildData, schedule::Schedule, sound::SoundCtx},
+    utils::{check_rate_limit, play_audio, queue_audio, start_idle_timer, Segment},
+    Data, Error,
+};
+
+/// How often the database is checked for schedules that are due
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Schedules missed by more than this, e.g. while the bot was offline, are skipped
+const MISSED_GRACE: i64 = 300;
+
+/// What became of a schedule that was due
+enum Outcome {
+    /// The schedule ran, or can't run, and moves on to its next run
+    Finished,
+    /// The bot is in another voice channel in the guild. The run is tried again on the next poll,
+    /// until it has been missed
+    Busy,
+}
+
+/// Run due schedules until the process exits
+pub async fn run(ctx: serenity_prelude::Context, data: Data) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    // schedules that are still being played, so that a slow run isn't started again
+    let running = Arc::new(Mutex::new(HashSet::new()));
+
+    loop {
+        interval.tick().await;
+
+        let now = Utc::now().naive_utc();
+
+        let due = match Schedule::due(now, &data.database).await {
+            Ok(due) => due,
+
+            Err(e) => {
+                warn!("Failed to fetch due schedules: {}", e);
+
+                continue;
+            }
+        };
+
+        for schedule in due {
+            if !running.lock().unwrap().insert(schedule.id) {
+                continue;
+            }
+
+            let ctx = ctx.clone();
+            let data = data.clone();
+            let running = running.clone();
+
+            // each run is spawned, so one slow guild doesn't hold up the others
+            tokio::spawn(async move {
+                let id = schedule.id;
+
+                handle_due(&ctx, &data, schedule, now).await;
+
+                running.lock().unwrap().remove(&id);
+            });
+        }
+    }
+}
+
+/// Run a schedule that is due, or skip it if it was missed, and then move it on to its next run
+async fn handle_due(
+    ctx: &serenity_prelude::Context,
+    data: &Data,
+    mut schedule: Schedule,
+    now: NaiveDateTime,
+) {
+    let missed = (now - schedule.next_run).num_seconds() > MISSED_GRACE;
+
+    if missed {
+        info!(
+            "Skipping schedule {} in guild {}, missed at {}",
+            schedule.id, schedule.guild_id, schedule.next_run
+        );
+
+        if schedule.recurrence.is_none() {
+            notify_missed(ctx, &schedule).await;
+        }
+    } else {
+        match run_schedule(ctx, data, &schedule).await {
+            Ok(Outcome::Finished) => {}
+
+            Ok(Outcome::Busy) => return,
+
+            Err(e) => {
+                warn!(
+                    "Failed to run schedule {} in guild {}: {}",
+                    schedule.id, schedule.guild_id, e
+                );
+            }
+        }
+    }
+
+    let advanced = match schedule.next_after(now) {
+        Some(next_run) => schedule.reschedule(next_run, &data.database).await,
+
+        None => schedule.delete(&data.database).await,
+    };
+
+    if let Err(e) = advanced {
+        warn!("Failed to advance schedule {}: {}", schedule.id, e);
+    }
+}
+
+/// Tell the creator of a one-off schedule that it was missed, as it is removed without playing
+async fn notify_missed(ctx: &serenity_prelude::Context, schedule: &Schedule) {
+    let guild_name = GuildId(schedule.guild_id)
+        .name(ctx)
+        .unwrap_or_else(|| "a server".to_string());
+
+    let content = format!(
+        "Your sounds scheduled in {} for <t:{}:F> couldn't be played in time, so the schedule has been removed",
+        guild_name,
+        schedule.next_run.timestamp()
+    );
+
+    let sent = match UserId(schedule.user_id).create_dm_channel(ctx).await {
+        Ok(channel) => channel.say(ctx, content).await.map(|_| ()),
+
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = sent {
+        warn!(
+            "Failed to tell user {} about missed schedule {}: {}",
+            schedule.user_id, schedule.id, e
+        );
+    }
+}
+
+/// The schedule's creator, if they can still connect and speak in its channel as they would need
+/// to for `/play`
+async fn speaking_member(
+    ctx: &serenity_prelude::Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> Result<Option<Member>, Error> {
+    let (guild, channel) = match (
+        guild_id.to_guild_cached(ctx),
+        channel_id.to_channel_cached(ctx),
+    ) {
+        (Some(guild), Some(Channel::Guild(channel))) => (guild, channel),
+
+        // the channel has been deleted
+        _ => return Ok(None),
+    };
+
+    // members aren't all cached, so they are fetched to see their current roles
+    let member = guild_id.member(ctx, user_id).await?;
+    let permissions = guild.user_permissions_in(&channel, &member)?;
+
+    Ok((permissions.connect() && permissions.speak()).then(|| member))
+}
+
+async fn run_schedule(
+    ctx: &serenity_prelude::Context,
+    data: &Data,
+    schedule: &Schedule,
+) -> Result<Outcome, Error> {
+    let guild_id = GuildId(schedule.guild_id);
+    let channel_id = ChannelId(schedule.channel_id);
+    let user_id = UserId(schedule.user_id);
+
+    let member = match speaking_member(ctx, guild_id, channel_id, user_id).await? {
+        Some(member) => member,
+
+        None => {
+            info!(
+                "Skipping schedule {}, user {} can't speak in channel {}",
+                schedule.id, user_id, channel_id
+            );
+
+            return Ok(Outcome::Finished);
+        }
+    };
+
+    let guild_data = data.guild_data(guild_id).await?.read().await.clone();
+
+    // sounds are looked up again, as they may have been made private since being scheduled
+    let mut sounds = vec![];
+    for scheduled in schedule.sounds(&data.database).await? {
+        let search = data
+            .search_for_sound(&scheduled.id.to_string(), guild_id, user_id, true)
+            .await?;
+
+        if let Some(sound) = search.into_iter().next() {
+            if !sound.too_long(guild_data.max_play_duration) {
+                sounds.push(sound);
+            }
+        }
+    }
+
+    if sounds.is_empty() {
+        return Ok(Outcome::Finished);
+    }
+
+    let current_call = match data.songbird.get(guild_id) {
+        Some(call) => {
+            let current_channel = call.lock().await.current_channel();
+
+            match current_channel {
+                Some(current_channel) if current_channel == channel_id.into() => Some(call),
+
+                // joining would pull the bot out of a call people are using
+                Some(_) => return Ok(Outcome::Busy),
+
+                None => None,
+            }
+        }
+
+        None => None,
+    };
+
+    // scheduled plays count towards the creator's rate limit like any other play. Checked once
+    // the run can't be put off, so that a deferred run isn't counted twice
+    check_rate_limit(data, &member, &guild_data).await?;
+
+    let call = match current_call {
+        Some(call) => call,
+
+        None => {
+            let (call, res) = data.songbird.join(guild_id, channel_id).await;
+            res?;
+            start_idle_timer(data, guild_id).await;
+
+            call
+        }
+    };
+
+    let mut lock = call.lock().await;
+    let _ = lock.deafen(true).await;
+
+    match sounds.as_slice() {
+        [sound] => {
+            play_audio(
+                sound,
+                user_id,
+                &guild_data,
+                &mut lock,
+                data,
+                None,
+                Segment::default(),
+                &Effects::default(),
+            )
+            .await?;
+        }
+
+        sounds => {
+            for (sound, e) in queue_audio(
+                sounds,
+                user_id,
+                &guild_data,
+                &mut lock,
+                data,
+                &Effects::default(),
+            )
+            .await
+            {
+                warn!(
+                    "Failed to queue sound {} for schedule {}: {}",
+                    sound.id, schedule.id, e
+                );
+            }
+        }
+    }
+
+    Ok(Outcome::Finished)
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use log::{info, warn};
+use tokio::fs;
+
+use crate::{storage::content_hash, Error};
+
+struct CacheEntry {
+    path: PathBuf,
+    sound_id: u32,
+    size: u64,
+    checksum: String,
+    last_used: u64,
+    verified: bool,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total_size: u64,
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, key: String, entry: CacheEntry) -> Option<CacheEntry> {
+        self.total_size += entry.size;
+
+        let old = self.entries.insert(key, entry);
+        if let Some(old) = &old {
+            self.total_size -= old.size;
+        }
+
+        old
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let old = self.entries.remove(key);
+        if let Some(old) = &old {
+            self.total_size -= old.size;
+        }
+
+        old
+    }
+
+    /// Remove least recently used entries until the index fits within `max_size`, never
+    /// removing `keep`. Returns the paths of the files that should be deleted
+    fn evict(&mut self, max_size: u64, keep: &str) -> Vec<PathBuf> {
+        let mut evicted = vec![];
+
+        while self.total_size > max_size {
+            let lru = self
+                .entries
+                .iter()
+                .filter(|(k, _)| k.as_str() != keep)
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone());
+
+            match lru.and_then(|k| self.remove(&k)) {
+                Some(entry) => evicted.push(entry.path),
+
+                None => break,
+            }
+        }
+
+        evicted
+    }
+}
+
+/// Bounded on-disk cache of sound files, evicted least-recently-used first.
+///
+/// Files are named `sound-{id}[-{variant}].{sha256}` so the index can be rebuilt and verified
+/// after a restart. Variants hold derived copies of a sound, such as transcoded downloads
+pub struct SoundCache {
+    location: PathBuf,
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+    tmp_counter: AtomicU64,
+}
+
+impl SoundCache {
+    pub fn new<P: AsRef<Path>>(location: P, max_size: u64) -> Self {
+        Self {
+            location: location.as_ref().to_path_buf(),
+            max_size,
+            index: Mutex::new(CacheIndex::default()),
+            tmp_counter: AtomicU64::new(0),
+        }
+    }
+
+    fn key(sound_id: u32, variant: Option<&str>) -> String {
+        match variant {
+            Some(variant) => format!("sound-{}-{}", sound_id, variant),
+
+            None => format!("sound-{}", sound_id),
+        }
+    }
+
+    /// Rebuild the index from files left in the cache directory by a previous run
+    pub async fn load(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.location).await?;
+
+        let mut dir = fs::read_dir(&self.location).await?;
+        let mut loaded = 0;
+
+        while let Some(file) = dir.next_entry().await? {
+            let file_name = file.file_name().to_string_lossy().to_string();
+
+            if file_name.starts_with(".tmp-sound-") {
+                let _ = fs::remove_file(file.path()).await;
+                continue;
+            }
+
+            // the directory may be shared, so files the cache didn't write are left alone
+            let rest = match file_name.strip_prefix("sound-") {
+                Some(rest) => rest,
+
+                None => continue,
+            };
+
+            let parsed = rest.rsplit_once('.').and_then(|(key, checksum)| {
+                let id = key.split('-').next()?.parse::<u32>().ok()?;
+
+                Some((format!("sound-{}", key), id, checksum.to_string()))
+            });
+
+            // files from older versions don't carry a checksum, so can't be trusted
+            let (key, sound_id, checksum) = match parsed {
+                Some(p) if p.2.len() == 64 => p,
+
+                _ => {
+                    let _ = fs::remove_file(file.path()).await;
+                    continue;
+                }
+            };
+
+            let size = file.metadata().await?.len();
+
+            {
+                let mut index = self.index.lock().unwrap();
+                let last_used = index.tick();
+
+                index.insert(
+                    key,
+                    CacheEntry {
+                        path: file.path(),
+                        sound_id,
+                        size,
+                        checksum,
+                        last_used,
+                        verified: false,
+                    },
+                );
+            }
+            loaded += 1;
+        }
+
+        let evicted = self.index.lock().unwrap().evict(self.max_size, "");
+        for path in evicted {
+            let _ = fs::remove_file(path).await;
+        }
+
+        info!("Loaded {} cached sound files", loaded);
+
+        Ok(())
+    }
+
+    /// Verifies the file's checksum the first time it's used
+    pub async fn get(&self, sound_id: u32, variant: Option<&str>) -> Option<PathBuf> {
+        let key = Self::key(sound_id, variant);
+
+        let (path, checksum) = {
+            let mut index = self.index.lock().unwrap();
+            let now = index.tick();
+
+            let entry = index.entries.get_mut(&key)?;
+            entry.last_used = now;
+
+            if entry.verified {
+                return Some(entry.path.clone());
+            }
+
+            (entry.path.clone(), entry.checksum.clone())
+        };
+
+        let valid = match fs::read(&path).await {
+            Ok(data) => content_hash(&data) == checksum,
+
+            Err(_) => false,
+        };
+
+        if valid {
+            if let Some(entry) = self.index.lock().unwrap().entries.get_mut(&key) {
+                entry.verified = true;
+            }
+
+            Some(path)
+        } else {
+            warn!("Cached file {:?} failed verification. Discarding", path);
+
+            {
+                let mut index = self.index.lock().unwrap();
+
+                if index.entries.get(&key).map_or(false, |e| e.path == path) {
+                    index.remove(&key);
+                }
+            }
+
+            remove_if_exists(&path).await;
+
+            None
+        }
+    }
+
+    /// Atomically write a file into the cache, evicting old files if the cache is over budget
+    pub async fn insert(
+        &self,
+        sound_id: u32,
+        variant: Option<&str>,
+        data: &[u8],
+    ) -> Result<PathBuf, Error> {
+        let key = Self::key(sound_id, variant);
+        let checksum = content_hash(data);
+        let path = self.location.join(format!("{}.{}", key, checksum));
+
+        // write to a temporary file and rename it, so that concurrent plays never read a
+        // partially written file
+        let tmp_path = self.location.join(format!(
+            ".tmp-{}-{}",
+            key,
+            self.tmp_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        fs::create_dir_all(&self.location).await?;
+        fs::write(&tmp_path, data).await?;
+
+        if let Err(e) = fs::rename(&tmp_path, &path).await {
+            let _ = fs::remove_file(&tmp_path).await;
+
+            return Err(e.into());
+        }
+
+        let stale = {
+            let mut index = self.index.lock().unwrap();
+            let last_used = index.tick();
+
+            let old = index.insert(
+                key.clone(),
+                CacheEntry {
+                    path: path.clone(),
+                    sound_id,
+                    size: data.len() as u64,
+                    checksum,
+                    last_used,
+                    verified: true,
+                },
+            );
+
+            let mut stale = index.evict(self.max_size, &key);
+            if let Some(old) = old {
+                if old.path != path {
+                    stale.push(old.path);
+                }
+            }
+
+            stale
+        };
+
+        for stale_path in stale {
+            remove_if_exists(&stale_path).await;
+        }
+
+        Ok(path)
+    }
+
+    pub async fn invalidate_sound(&self, sound_id: u32) {
+        let paths = {
+            let mut index = self.index.lock().unwrap();
+
+            let keys = index
+                .entries
+                .iter()
+                .filter(|(_, e)| e.sound_id == sound_id)
+                .map(|(k, _)| k.clone())
+                .collect::<Vec<String>>();
+
+            keys.iter()
+                .filter_map(|k| index.remove(k))
+                .map(|e| e.path)
+                .collect::<Vec<PathBuf>>()
+        };
+
+        for path in paths {
+            remove_if_exists(&path).await;
+        }
+    }
+}
+
+async fn remove_if_exists(path: &Path) {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            warn!("Failed to remove cached file {:?}: {:?}", path, e);
+        }
+
+        _ => {}
+    }
+}
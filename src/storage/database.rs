@@ -0,0 +1,63 @@
+use poise::serenity_prelude::async_trait;
+use sqlx::Pool;
+
+use crate::{storage::SoundStore, Database, Error};
+
+/// Stores audio in the `src` column of the `sound_sources` table
+pub struct DatabaseStore {
+    database: Pool<Database>,
+}
+
+impl DatabaseStore {
+    pub fn new(database: Pool<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl SoundStore for DatabaseStore {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, Error> {
+        struct Src {
+            src: Option<Vec<u8>>,
+        }
+
+        let record = sqlx::query_as_unchecked!(
+            Src,
+            "
+SELECT src
+    FROM sound_sources
+    WHERE hash = ?
+    LIMIT 1
+            ",
+            hash
+        )
+        .fetch_optional(&self.database)
+        .await?;
+
+        Ok(record.and_then(|r| r.src))
+    }
+
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE sound_sources SET src = ? WHERE hash = ?",
+            data,
+            hash
+        )
+        .execute(&self.database)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), Error> {
+        sqlx::query!("UPDATE sound_sources SET src = NULL WHERE hash = ?", hash)
+            .execute(&self.database)
+            .await?;
+
+        Ok(())
+    }
+}
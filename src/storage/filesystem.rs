@@ -0,0 +1,63 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use poise::serenity_prelude::async_trait;
+use tokio::fs;
+
+use crate::{storage::SoundStore, Error};
+
+/// Stores audio as one file per content hash in a local directory
+pub struct FilesystemStore {
+    root: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(format!("{}.opus", hash))
+    }
+}
+
+#[async_trait]
+impl SoundStore for FilesystemStore {
+    fn name(&self) -> &'static str {
+        "filesystem"
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.path(hash)).await {
+            Ok(data) => Ok(Some(data)),
+
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), Error> {
+        fs::create_dir_all(&self.root).await?;
+
+        // write to a temporary file first so that readers never see a partial file
+        let tmp_path = self.root.join(format!("{}.opus.tmp", hash));
+
+        fs::write(&tmp_path, data).await?;
+        fs::rename(&tmp_path, self.path(hash)).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(hash)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+
+            _ => Ok(()),
+        }
+    }
+}
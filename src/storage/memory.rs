@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use songbird::input::{cached::Compressed, Input};
+
+/// Number of recorded plays after which all play counts are halved, so that sounds which were
+/// popular a long time ago don't hold their place in the cache forever
+const FREQUENCY_DECAY_INTERVAL: u64 = 1000;
+
+struct MemoryEntry {
+    source: Compressed,
+    sound_id: u32,
+    size: u64,
+}
+
+#[derive(Default)]
+struct MemoryIndex {
+    entries: HashMap<String, MemoryEntry>,
+    frequency: HashMap<String, u32>,
+    total_size: u64,
+    plays: u64,
+}
+
+impl MemoryIndex {
+    fn record_play(&mut self, key: &str) {
+        *self.frequency.entry(key.to_string()).or_insert(0) += 1;
+        self.plays += 1;
+
+        if self.plays % FREQUENCY_DECAY_INTERVAL == 0 {
+            self.frequency.values_mut().for_each(|count| *count /= 2);
+
+            let entries = &self.entries;
+            self.frequency
+                .retain(|key, count| *count > 0 || entries.contains_key(key));
+        }
+    }
+
+    fn frequency(&self, key: &str) -> u32 {
+        self.frequency.get(key).copied().unwrap_or(0)
+    }
+
+    /// Pick the least frequently played entries that would need to be removed to fit `size`
+    /// more bytes within `max_size`. Returns `None` if any of them are played at least as often
+    /// as `key`, in which case `key` shouldn't displace them
+    fn victims(&self, key: &str, size: u64, max_size: u64) -> Option<Vec<String>> {
+        let mut candidates = self
+            .entries
+            .iter()
+            .filter(|(k, _)| k.as_str() != key)
+            .map(|(k, e)| (k, e.size, self.frequency(k)))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(_, _, frequency)| *frequency);
+
+        let frequency = self.frequency(key);
+        let mut total_size = self.total_size + size;
+        let mut victims = vec![];
+
+        for (k, entry_size, entry_frequency) in candidates {
+            if total_size <= max_size {
+                break;
+            }
+
+            if entry_frequency >= frequency {
+                return None;
+            }
+
+            total_size -= entry_size;
+            victims.push(k.clone());
+        }
+
+        Some(victims)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<MemoryEntry> {
+        let old = self.entries.remove(key);
+        if let Some(old) = &old {
+            self.total_size -= old.size;
+        }
+
+        old
+    }
+}
+
+pub struct MemoryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub admissions: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub size: u64,
+    pub max_size: u64,
+}
+
+impl MemoryCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            0.0
+        } else {
+            self.hits as f64 / (self.hits + self.misses) as f64
+        }
+    }
+}
+
+/// Holds compressed audio of frequently played sounds in memory, so that playing them doesn't
+/// need to start ffmpeg. A sound is only admitted once it has been played `admit_after` times,
+/// and only displaces entries that are played less often than it
+pub struct MemoryCache {
+    max_size: u64,
+    admit_after: u32,
+    index: Mutex<MemoryIndex>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    admissions: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl MemoryCache {
+    pub fn new(max_size: u64, admit_after: u32) -> Self {
+        Self {
+            max_size,
+            admit_after,
+            index: Mutex::new(MemoryIndex::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            admissions: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn key(sound_id: u32, variant: Option<&str>) -> String {
+        match variant {
+            Some(variant) => format!("{}-{}", sound_id, variant),
+
+            None => sound_id.to_string(),
+        }
+    }
+
+    /// Records a play of the sound, as well as reading it from memory if it is cached
+    pub fn get(&self, sound_id: u32, variant: Option<&str>) -> Option<Input> {
+        let key = Self::key(sound_id, variant);
+        let mut index = self.index.lock().unwrap();
+
+        index.record_play(&key);
+
+        match index.entries.get(&key) {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+
+                Some(entry.source.new_handle().into())
+            }
+
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+
+                None
+            }
+        }
+    }
+
+    /// Whether a sound of `size` bytes has been played often enough to be worth caching
+    pub fn should_admit(&self, sound_id: u32, variant: Option<&str>, size: u64) -> bool {
+        if size > self.max_size {
+            return false;
+        }
+
+        let key = Self::key(sound_id, variant);
+        let index = self.index.lock().unwrap();
+
+        index.frequency(&key) >= self.admit_after
+            && index.victims(&key, size, self.max_size).is_some()
+    }
+
+    /// Add a sound to the cache, evicting less frequently played sounds to make room. `size` is
+    /// an estimate of the memory used by `source`
+    pub fn insert(&self, sound_id: u32, variant: Option<&str>, source: Compressed, size: u64) {
+        let key = Self::key(sound_id, variant);
+        let mut index = self.index.lock().unwrap();
+
+        // another play may have changed the cache since `should_admit` was checked
+        let victims = match index.victims(&key, size, self.max_size) {
+            Some(victims) if size <= self.max_size => victims,
+
+            _ => return,
+        };
+
+        for victim in victims {
+            index.remove(&victim);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        index.remove(&key);
+        index.total_size += size;
+        index.entries.insert(
+            key,
+            MemoryEntry {
+                source,
+                sound_id,
+                size,
+            },
+        );
+
+        self.admissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn invalidate_sound(&self, sound_id: u32) {
+        let mut index = self.index.lock().unwrap();
+
+        let keys = index
+            .entries
+            .iter()
+            .filter(|(_, e)| e.sound_id == sound_id)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            index.remove(&key);
+        }
+    }
+
+    pub fn stats(&self) -> MemoryCacheStats {
+        let index = self.index.lock().unwrap();
+
+        MemoryCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            admissions: self.admissions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: index.entries.len(),
+            size: index.total_size,
+            max_size: self.max_size,
+        }
+    }
+}
@@ -0,0 +1,298 @@
+mod cache;
+mod database;
+mod filesystem;
+mod memory;
+mod s3;
+
+use std::{env, sync::Arc};
+
+use log::{info, warn};
+use poise::serenity_prelude::async_trait;
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Pool};
+
+pub use self::{
+    cache::SoundCache, database::DatabaseStore, filesystem::FilesystemStore, memory::MemoryCache,
+    s3::S3Store,
+};
+use crate::{Database, Error};
+
+/// A backend that holds encoded audio, keyed by the SHA-256 hash of its content
+#[async_trait]
+pub trait SoundStore: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns `Ok(None)` if the backend holds nothing for the hash
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Removing audio that doesn't exist is not an error
+    async fn delete(&self, hash: &str) -> Result<(), Error>;
+}
+
+pub fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Hold a MySQL named lock on a hash, so that references to the same audio are never taken and
+/// dropped at the same time. A named lock is used rather than a row lock, as the database store
+/// writes to the row from its own connection
+async fn lock_source(connection: &mut PoolConnection<Database>, hash: &str) -> Result<(), Error> {
+    let locked = sqlx::query!("SELECT GET_LOCK(?, 30) AS locked", hash)
+        .fetch_one(&mut *connection)
+        .await?
+        .locked;
+
+    if locked == Some(1) {
+        Ok(())
+    } else {
+        Err(format!("Timed out waiting for the lock on source {}", hash).into())
+    }
+}
+
+async fn unlock_source(connection: &mut PoolConnection<Database>, hash: &str) {
+    if let Err(e) = sqlx::query!("SELECT RELEASE_LOCK(?)", hash)
+        .execute(&mut *connection)
+        .await
+    {
+        warn!("Failed to release the lock on source {}: {}", hash, e);
+    }
+}
+
+/// Take a reference to some audio, storing it only if no other sound already references the
+/// same content. Returns the content hash to be recorded against the sound
+pub async fn acquire(
+    store: &dyn SoundStore,
+    database: &Pool<Database>,
+    data: &[u8],
+) -> Result<String, Error> {
+    let hash = content_hash(data);
+
+    let mut connection = database.acquire().await?;
+    lock_source(&mut connection, &hash).await?;
+
+    let acquired = take_reference(store, &mut connection, &hash, data).await;
+
+    unlock_source(&mut connection, &hash).await;
+
+    acquired.map(|_| hash)
+}
+
+async fn take_reference(
+    store: &dyn SoundStore,
+    connection: &mut PoolConnection<Database>,
+    hash: &str,
+    data: &[u8],
+) -> Result<(), Error> {
+    let referenced = sqlx::query!(
+        "UPDATE sound_sources SET ref_count = ref_count + 1 WHERE hash = ? AND ref_count > 0",
+        hash
+    )
+    .execute(&mut *connection)
+    .await?
+    .rows_affected()
+        == 1;
+
+    if referenced {
+        return Ok(());
+    }
+
+    // a row with no references is left by an upload that failed part way, and is reused. Its
+    // count stays at 0 until the audio is stored, so the hash is never handed out without it
+    sqlx::query!(
+        "INSERT IGNORE INTO sound_sources (hash, ref_count) VALUES (?, 0)",
+        hash
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    if let Err(e) = store.put(hash, data).await {
+        sqlx::query!(
+            "DELETE FROM sound_sources WHERE hash = ? AND ref_count = 0",
+            hash
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        return Err(e);
+    }
+
+    sqlx::query!(
+        "UPDATE sound_sources SET ref_count = 1 WHERE hash = ?",
+        hash
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Drop a reference to some audio, removing it from the store once nothing references it
+pub async fn release(
+    store: &dyn SoundStore,
+    database: &Pool<Database>,
+    hash: &str,
+) -> Result<(), Error> {
+    let mut connection = database.acquire().await?;
+    lock_source(&mut connection, hash).await?;
+
+    let released = drop_reference(store, &mut connection, hash).await;
+
+    unlock_source(&mut connection, hash).await;
+
+    released
+}
+
+async fn drop_reference(
+    store: &dyn SoundStore,
+    connection: &mut PoolConnection<Database>,
+    hash: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE sound_sources SET ref_count = ref_count - 1 WHERE hash = ? AND ref_count > 0",
+        hash
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    let unreferenced = sqlx::query!("SELECT ref_count FROM sound_sources WHERE hash = ?", hash)
+        .fetch_optional(&mut *connection)
+        .await?
+        .map_or(false, |r| r.ref_count == 0);
+
+    // the named lock stops the hash being taken again until both are gone
+    if unreferenced {
+        store.delete(hash).await?;
+
+        sqlx::query!(
+            "DELETE FROM sound_sources WHERE hash = ? AND ref_count = 0",
+            hash
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Move audio left under sound IDs by versions before sources were shared to its content hash.
+/// Each sound is only tried once, as ones with nothing under their ID never had audio stored
+pub async fn upgrade_legacy_keys(
+    store: &dyn SoundStore,
+    database: &Pool<Database>,
+) -> Result<(), Error> {
+    let legacy = sqlx::query!("SELECT id FROM sounds WHERE legacy_source = 1")
+        .fetch_all(database)
+        .await?;
+
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    let mut moved = 0;
+
+    for record in &legacy {
+        let key = record.id.to_string();
+
+        match store.get(&key).await? {
+            Some(data) => {
+                let hash = acquire(store, database, &data).await?;
+
+                sqlx::query!(
+                    "UPDATE sounds SET source_hash = ?, legacy_source = 0 WHERE id = ?",
+                    hash,
+                    record.id
+                )
+                .execute(database)
+                .await?;
+
+                store.delete(&key).await?;
+
+                moved += 1;
+            }
+
+            None => {
+                warn!(
+                    "Sound {} has no audio under its ID in the {} store",
+                    record.id,
+                    store.name()
+                );
+
+                sqlx::query!(
+                    "UPDATE sounds SET legacy_source = 0 WHERE id = ?",
+                    record.id
+                )
+                .execute(database)
+                .await?;
+            }
+        }
+    }
+
+    info!(
+        "Moved {} of {} sounds in the {} store from ID to hash keys",
+        moved,
+        legacy.len(),
+        store.name()
+    );
+
+    Ok(())
+}
+
+/// Construct a store by name, reading any backend-specific configuration from the environment
+pub fn store_from_name(name: &str, database: Pool<Database>) -> Result<Arc<dyn SoundStore>, Error> {
+    match name {
+        "database" => Ok(Arc::new(DatabaseStore::new(database))),
+
+        "filesystem" => Ok(Arc::new(FilesystemStore::new(
+            env::var("SOUND_STORE_PATH")
+                .unwrap_or_else(|_| "/var/lib/soundfx-rs/sounds".to_string()),
+        ))),
+
+        "s3" => Ok(Arc::new(S3Store::from_env()?)),
+
+        _ => Err(format!("Unknown sound store '{}'", name).into()),
+    }
+}
+
+/// Copy all audio from one store into another, removing it from the source store once the
+/// copy has succeeded. Returns the number of files moved
+pub async fn migrate(
+    from: &dyn SoundStore,
+    to: &dyn SoundStore,
+    database: &Pool<Database>,
+) -> Result<u64, Error> {
+    let hashes = sqlx::query!("SELECT hash FROM sound_sources")
+        .fetch_all(database)
+        .await?;
+
+    let mut moved = 0;
+
+    for record in hashes {
+        match from.get(&record.hash).await? {
+            Some(data) => {
+                to.put(&record.hash, &data).await?;
+                from.delete(&record.hash).await?;
+
+                moved += 1;
+            }
+
+            None => {
+                warn!(
+                    "Source {} has no audio in the {} store. Skipping",
+                    record.hash,
+                    from.name()
+                );
+            }
+        }
+    }
+
+    info!(
+        "Moved {} files from the {} store to the {} store",
+        moved,
+        from.name(),
+        to.name()
+    );
+
+    Ok(moved)
+}
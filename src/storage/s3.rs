@@ -0,0 +1,87 @@
+use std::env;
+
+use poise::serenity_prelude::async_trait;
+use s3::{bucket::Bucket, creds::Credentials, Region};
+
+use crate::{storage::SoundStore, Error};
+
+/// Stores audio in an S3-compatible object store, such as AWS S3 or MinIO
+pub struct S3Store {
+    bucket: Bucket,
+    prefix: String,
+}
+
+impl S3Store {
+    pub fn from_env() -> Result<Self, Error> {
+        let bucket_name =
+            env::var("S3_BUCKET").map_err(|_| "Missing S3_BUCKET from environment")?;
+        let region = match env::var("S3_ENDPOINT") {
+            Ok(endpoint) => Region::Custom {
+                region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                endpoint,
+            },
+
+            Err(_) => env::var("S3_REGION")
+                .unwrap_or_else(|_| "us-east-1".to_string())
+                .parse()?,
+        };
+        let credentials = Credentials::new(
+            env::var("S3_ACCESS_KEY").ok().as_deref(),
+            env::var("S3_SECRET_KEY").ok().as_deref(),
+            None,
+            None,
+            None,
+        )?;
+
+        // MinIO and most self-hosted stores don't support virtual-hosted buckets
+        let bucket = Bucket::new(&bucket_name, region, credentials)?.with_path_style();
+
+        Ok(Self {
+            bucket,
+            prefix: env::var("S3_PREFIX").unwrap_or_else(|_| "sounds".to_string()),
+        })
+    }
+
+    fn key(&self, hash: &str) -> String {
+        format!("{}/{}.opus", self.prefix, hash)
+    }
+}
+
+#[async_trait]
+impl SoundStore for S3Store {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, Error> {
+        let response = self.bucket.get_object(self.key(hash)).await?;
+
+        match response.status_code() {
+            200 => Ok(Some(response.bytes().to_vec())),
+
+            404 => Ok(None),
+
+            code => Err(format!("S3 returned status {} fetching {}", code, hash).into()),
+        }
+    }
+
+    async fn put(&self, hash: &str, data: &[u8]) -> Result<(), Error> {
+        let response = self.bucket.put_object(self.key(hash), data).await?;
+
+        match response.status_code() {
+            200 => Ok(()),
+
+            code => Err(format!("S3 returned status {} storing {}", code, hash).into()),
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), Error> {
+        let response = self.bucket.delete_object(self.key(hash)).await?;
+
+        match response.status_code() {
+            200 | 204 | 404 => Ok(()),
+
+            code => Err(format!("S3 returned status {} deleting {}", code, hash).into()),
+        }
+    }
+}
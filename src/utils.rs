@@ -1,63 +1,715 @@
-use std::sync::Arc;
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use poise::serenity_prelude::model::{
-    channel::Channel,
-    guild::Guild,
-    id::{ChannelId, UserId},
+use dashmap::DashMap;
+use log::{info, warn};
+use poise::serenity_prelude::{
+    async_trait,
+    model::{
+        channel::Channel,
+        guild::{Guild, Member},
+        id::{ChannelId, GuildId, UserId},
+    },
+    TypeMapKey,
+};
+use songbird::{
+    create_player,
+    error::JoinResult,
+    tracks::{PlayMode, Track, TrackHandle, TrackResult},
+    Call, Event, EventContext, EventHandler, TrackEvent,
 };
-use songbird::{create_player, error::JoinResult, tracks::TrackHandle, Call};
-use sqlx::Executor;
 use tokio::sync::{Mutex, MutexGuard};
 
 use crate::{
+    audio::Effects,
+    cmds::now_playing::{attach_panel_events, close_panel},
+    error::ErrorTypes,
     models::{
-        guild_data::CtxGuildData,
+        guild_data::{CtxGuildData, GuildData, PlaybackMode},
         sound::{Sound, SoundCtx},
     },
-    Data, Database,
+    Data, Error,
 };
 
+/// Details of the sound a track is playing, stored in the track's typemap
+#[derive(Clone)]
+pub struct TrackInfo {
+    pub sound_id: u32,
+    pub name: String,
+    pub duration: Option<f64>,
+    pub user_id: UserId,
+}
+
+impl TypeMapKey for TrackInfo {
+    type Value = TrackInfo;
+}
+
+impl TrackInfo {
+    pub async fn of(handle: &TrackHandle) -> Option<TrackInfo> {
+        handle.typemap().read().await.get::<TrackInfo>().cloned()
+    }
+
+    async fn attach(handle: &TrackHandle, sound: &Sound, user_id: UserId) {
+        handle
+            .typemap()
+            .write()
+            .await
+            .insert::<TrackInfo>(TrackInfo {
+                sound_id: sound.id,
+                name: sound.name.clone(),
+                duration: sound.duration,
+                user_id,
+            });
+    }
+}
+
+/// Limits on how long a sound loops for. With no limits, the sound loops until stopped
+#[derive(Clone, Copy, Default)]
+pub struct Loop {
+    /// Number of times the sound repeats after it first plays
+    pub count: Option<usize>,
+    /// Time after which the sound stops, however many times it has repeated
+    pub max_duration: Option<Duration>,
+}
+
+/// Length in seconds of one frame of songbird's audio
+const FRAME_LENGTH: f64 = 0.02;
+
+/// Part of a sound to play, in seconds of the original sound. By default, the whole sound plays
+#[derive(Clone, Copy, Default)]
+pub struct Segment {
+    /// Time into the sound to start playing from
+    pub start: Duration,
+    /// Time after which playback is cut, or restarts from `start` when looping
+    pub duration: Option<Duration>,
+}
+
+impl Segment {
+    fn is_whole(&self) -> bool {
+        self.start.is_zero() && self.duration.is_none()
+    }
+
+    /// The segment as it falls in the sound played at `speed`, which is where the track has to
+    /// seek to and how long it plays for
+    fn at_speed(&self, speed: f64) -> Segment {
+        Segment {
+            start: self.start.div_f64(speed),
+            duration: self.duration.map(|duration| duration.div_f64(speed)),
+        }
+    }
+
+    /// Seconds of a sound lasting `sound_duration` that will play each time through, if known
+    fn length(&self, sound_duration: Option<f64>) -> Option<f64> {
+        let remaining =
+            sound_duration.map(|duration| (duration - self.start.as_secs_f64()).max(0.0));
+
+        match (remaining, self.duration) {
+            (Some(remaining), Some(duration)) => Some(remaining.min(duration.as_secs_f64())),
+
+            (remaining, duration) => remaining.or_else(|| duration.map(|d| d.as_secs_f64())),
+        }
+    }
+
+    /// How long each repeat plays before seeking back to `start`, or None to loop the whole
+    /// sound. A segment running to the end of the sound seeks back a frame early, as the track
+    /// would otherwise end first
+    fn repeat_period(&self, sound_duration: Option<f64>) -> Option<Duration> {
+        if self.is_whole() {
+            return None;
+        }
+
+        let length = self.length(sound_duration)?;
+        let runs_to_end = match (sound_duration, self.duration) {
+            (Some(total), Some(duration)) => {
+                self.start.as_secs_f64() + duration.as_secs_f64() >= total
+            }
+
+            (Some(_), None) => true,
+
+            (None, _) => false,
+        };
+
+        let period = if runs_to_end {
+            length - FRAME_LENGTH
+        } else {
+            length
+        };
+
+        Some(Duration::from_secs_f64(period.max(FRAME_LENGTH)))
+    }
+}
+
+/// Boosts past full volume are rounded to the nearest of these, so that each sound only ever has a
+/// few boosted variants to transcode and cache
+const BOOST_STEPS: [f32; 6] = [1.0, 1.25, 1.5, 2.0, 3.0, 4.0];
+
+fn boost_step(volume: f32) -> f32 {
+    BOOST_STEPS
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            (a - volume)
+                .abs()
+                .partial_cmp(&(b - volume).abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .unwrap_or(1.0)
+}
+
+/// Combine the guild volume with a sound's gain. Track volume above 1.0 clips, so any boost
+/// past that is applied by ffmpeg behind a limiter instead
+fn playback_volume(sound: &Sound, guild_volume: u8, effects: &Effects) -> (f32, Effects) {
+    let volume = guild_volume as f32 / 100.0 * sound.gain;
+
+    if volume <= 1.0 {
+        return (volume, effects.clone());
+    }
+
+    match boost_step(volume) {
+        boost if boost > 1.0 => {
+            let effects = Effects {
+                gain: Some(boost as f64),
+                ..effects.clone()
+            };
+
+            (1.0, effects)
+        }
+
+        _ => (1.0, effects.clone()),
+    }
+}
+
+struct StopTrack;
+
+#[async_trait]
+impl EventHandler for StopTrack {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = ctx {
+            for (_, handle) in tracks.iter() {
+                let _ = handle.stop();
+            }
+        }
+
+        Some(Event::Cancel)
+    }
+}
+
+/// Seeks back to the start of a segment, stopping the track once there are no repeats left
+struct RepeatSegment {
+    start: Duration,
+    /// Repeats left, or None to repeat until stopped. Tracks using songbird's own looping keep
+    /// count there instead
+    remaining: Option<AtomicUsize>,
+}
+
+#[async_trait]
+impl EventHandler for RepeatSegment {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = ctx {
+            let repeat = self.remaining.as_ref().map_or(true, |remaining| {
+                remaining
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| r.checked_sub(1))
+                    .is_ok()
+            });
+
+            for (_, handle) in tracks.iter() {
+                let ended = handle.typemap().read().await.contains_key::<LoopEnded>();
+
+                let _ = if repeat && !ended {
+                    handle.seek_time(self.start)
+                } else {
+                    handle.stop()
+                };
+            }
+        }
+
+        None
+    }
+}
+
+/// Marks a track whose loop has been ended. Repeated segments check for it, as they don't use
+/// songbird's looping
+struct LoopEnded;
+
+impl TypeMapKey for LoopEnded {
+    type Value = ();
+}
+
+/// Marks a track repeating part of its sound by seeking, which songbird doesn't see as looping
+struct SegmentRepeat;
+
+impl TypeMapKey for SegmentRepeat {
+    type Value = ();
+}
+
+/// Let a looping track finish the repeat it is playing, and then stop
+pub async fn end_track_loop(handle: &TrackHandle) {
+    handle.typemap().write().await.insert::<LoopEnded>(());
+
+    let _ = handle.disable_loop();
+}
+
+/// Loop a track again, picking its segment repeat back up if it has one
+pub async fn resume_track_loop(handle: &TrackHandle) -> TrackResult<()> {
+    let mut typemap = handle.typemap().write().await;
+    typemap.remove::<LoopEnded>();
+
+    if typemap.contains_key::<SegmentRepeat>() {
+        Ok(())
+    } else {
+        handle.enable_loop()
+    }
+}
+
+/// Whether a track repeating part of its sound is still set to repeat, or None for any other
+/// track
+pub async fn segment_looping(handle: &TrackHandle) -> Option<bool> {
+    let typemap = handle.typemap().read().await;
+
+    typemap
+        .contains_key::<SegmentRepeat>()
+        .then(|| !typemap.contains_key::<LoopEnded>())
+}
+
+struct ForgetLoop {
+    data: Data,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl EventHandler for ForgetLoop {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = ctx {
+            let ended = tracks
+                .iter()
+                .map(|(_, handle)| handle.uuid())
+                .collect::<Vec<_>>();
+
+            if let Some(mut looping) = self.data.looping_tracks.get_mut(&self.guild_id) {
+                looping.retain(|handle| !ended.contains(&handle.uuid()));
+            }
+
+            self.data
+                .looping_tracks
+                .remove_if(&self.guild_id, |_, looping| looping.is_empty());
+        }
+
+        None
+    }
+}
+
+/// Add a track to the guild's looping tracks until it ends, so that `/endloop` can find it
+pub fn remember_loop(handle: &TrackHandle, data: &Data, guild_id: GuildId) {
+    let _ = handle.add_event(
+        Event::Track(TrackEvent::End),
+        ForgetLoop {
+            data: data.clone(),
+            guild_id,
+        },
+    );
+
+    data.looping_tracks
+        .entry(guild_id)
+        .or_default()
+        .push(handle.clone());
+}
+
+struct IdleCheck {
+    data: Data,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl EventHandler for IdleCheck {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        start_idle_timer(&self.data, self.guild_id).await;
+
+        None
+    }
+}
+
+fn attach_idle_check(handle: &TrackHandle, data: &Data, guild_id: GuildId) {
+    let _ = handle.add_event(
+        Event::Track(TrackEvent::End),
+        IdleCheck {
+            data: data.clone(),
+            guild_id,
+        },
+    );
+}
+
+pub fn cancel_idle_timer(data: &Data, guild_id: GuildId) {
+    if let Some((_, timer)) = data.idle_timers.remove(&guild_id) {
+        timer.abort();
+    }
+}
+
+/// Leave the guild's call after its idle timeout, unless something plays in the meantime
+pub async fn start_idle_timer(data: &Data, guild_id: GuildId) {
+    let timeout = match data.guild_data(guild_id).await {
+        Ok(guild_data) => guild_data.read().await.idle_timeout,
+
+        Err(e) => {
+            warn!("Failed to read idle timeout of guild {}: {}", guild_id, e);
+
+            return;
+        }
+    };
+
+    cancel_idle_timer(data, guild_id);
+
+    if let Some(timeout) = timeout {
+        let task_data = data.clone();
+
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(timeout as u64)).await;
+
+            // paused and looping sounds count as activity; their own end restarts the timer
+            if is_idle(&task_data, guild_id).await {
+                task_data.idle_timers.remove(&guild_id);
+                leave_call(&task_data, guild_id).await;
+
+                info!("Left voice in guild {} after {}s idle", guild_id, timeout);
+            }
+        });
+
+        data.idle_timers.insert(guild_id, timer);
+    }
+}
+
+async fn is_idle(data: &Data, guild_id: GuildId) -> bool {
+    let queued = match data.songbird.get(guild_id) {
+        Some(call) => !call.lock().await.queue().is_empty(),
+
+        None => return false,
+    };
+
+    !queued && live_tracks(&data.active_tracks, guild_id).await.is_empty()
+}
+
+/// Leave the guild's call and forget everything about it. Every way of leaving goes through here
+pub async fn leave_call(data: &Data, guild_id: GuildId) {
+    if let Err(e) = data.songbird.remove(guild_id).await {
+        warn!("Failed to leave voice in guild {}: {}", guild_id, e);
+    }
+
+    cancel_idle_timer(data, guild_id);
+    data.active_tracks.remove(&guild_id);
+    data.looping_tracks.remove(&guild_id);
+    close_panel(data, guild_id).await;
+}
+
+/// Tracks in a guild's entry of `tracks` that haven't finished, oldest first. Finished tracks
+/// are removed from the map
+pub async fn live_tracks(
+    tracks: &DashMap<GuildId, Vec<TrackHandle>>,
+    guild_id: GuildId,
+) -> Vec<TrackHandle> {
+    let handles = tracks
+        .get(&guild_id)
+        .map(|tracks| tracks.clone())
+        .unwrap_or_default();
+
+    let mut finished = vec![];
+    for handle in &handles {
+        let playing = handle.get_info().await.map_or(false, |state| {
+            matches!(state.playing, PlayMode::Play | PlayMode::Pause)
+        });
+
+        if !playing {
+            finished.push(handle.uuid());
+        }
+    }
+
+    match tracks.get_mut(&guild_id) {
+        Some(mut tracks) => {
+            tracks.retain(|handle| !finished.contains(&handle.uuid()));
+
+            tracks.clone()
+        }
+
+        None => vec![],
+    }
+}
+
 pub async fn play_audio(
     sound: &Sound,
-    volume: u8,
+    user_id: UserId,
+    guild_data: &GuildData,
     call_handler: &mut MutexGuard<'_, Call>,
-    db_pool: impl Executor<'_, Database = Database>,
-    loop_: bool,
-) -> Result<TrackHandle, Box<dyn std::error::Error + Send + Sync>> {
-    let (track, track_handler) = create_player(sound.playable(db_pool).await?.into());
+    data: &Data,
+    loop_: Option<Loop>,
+    segment: Segment,
+    effects: &Effects,
+) -> Result<TrackHandle, Error> {
+    let (track, track_handler) = load_track(sound, guild_data, data, effects).await?;
 
-    let _ = track_handler.set_volume(volume as f32 / 100.0);
+    // speed changes the length of the track, so the segment is moved to match
+    let speed = effects.speed.unwrap_or(1.0);
+    let played_duration = sound.duration.map(|duration| duration / speed);
 
-    if loop_ {
-        let _ = track_handler.enable_loop();
-    } else {
-        let _ = track_handler.disable_loop();
+    configure_track(
+        &track_handler,
+        sound,
+        user_id,
+        guild_data,
+        data,
+        loop_,
+        segment.at_speed(speed),
+        played_duration,
+    )
+    .await;
+
+    let guild_id = GuildId(guild_data.id);
+
+    match guild_data.playback_mode {
+        PlaybackMode::Overlap => {}
+
+        PlaybackMode::Interrupt => stop_active_tracks(data, guild_id),
+
+        PlaybackMode::Queue => {
+            call_handler.enqueue(track);
+
+            return Ok(track_handler);
+        }
+
+        PlaybackMode::Limited => make_room(data, guild_id, guild_data.overlap_limit, 1).await,
     }
 
-    call_handler.play(track);
+    start_track(track, &track_handler, call_handler, data, guild_id);
 
     Ok(track_handler)
 }
 
-pub async fn queue_audio(
+/// Play sounds at the same time. They are all loaded before any start, so that they line up,
+/// and so that nothing is left half set up if one fails to load
+pub async fn play_together(
     sounds: &[Sound],
-    volume: u8,
+    user_id: UserId,
+    guild_data: &GuildData,
     call_handler: &mut MutexGuard<'_, Call>,
-    db_pool: impl Executor<'_, Database = Database> + Copy,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    data: &Data,
+    effects: &Effects,
+) -> Result<(), Error> {
+    let guild_id = GuildId(guild_data.id);
+
+    let mut tracks = vec![];
     for sound in sounds {
-        let (a, b) = create_player(sound.playable(db_pool).await?.into());
+        tracks.push(load_track(sound, guild_data, data, effects).await?);
+    }
+
+    match guild_data.playback_mode {
+        PlaybackMode::Overlap => {}
+
+        // `play_from_query` refuses groups in queue mode, so this only plays over a queue when
+        // called directly
+        PlaybackMode::Queue => {}
+
+        PlaybackMode::Interrupt => stop_active_tracks(data, guild_id),
 
-        let _ = b.set_volume(volume as f32 / 100.0);
+        PlaybackMode::Limited => {
+            make_room(data, guild_id, guild_data.overlap_limit, tracks.len()).await
+        }
+    }
+
+    for (sound, (track, track_handler)) in sounds.iter().zip(tracks) {
+        configure_track(
+            &track_handler,
+            sound,
+            user_id,
+            guild_data,
+            data,
+            None,
+            Segment::default(),
+            None,
+        )
+        .await;
 
-        call_handler.enqueue(a);
+        start_track(track, &track_handler, call_handler, data, guild_id);
     }
 
     Ok(())
 }
 
+async fn load_track(
+    sound: &Sound,
+    guild_data: &GuildData,
+    data: &Data,
+    effects: &Effects,
+) -> Result<(Track, TrackHandle), Error> {
+    let (volume, effects) = playback_volume(sound, guild_data.volume, effects);
+    let (track, track_handler) = create_player(sound.playable(data, &effects).await?);
+
+    let _ = track_handler.set_volume(volume);
+
+    Ok((track, track_handler))
+}
+
+/// Attach the events and position a loaded track needs. Only called once a track is sure to
+/// be played, as it stops the guild's idle timer
+async fn configure_track(
+    track_handler: &TrackHandle,
+    sound: &Sound,
+    user_id: UserId,
+    guild_data: &GuildData,
+    data: &Data,
+    loop_: Option<Loop>,
+    segment: Segment,
+    played_duration: Option<f64>,
+) {
+    let guild_id = GuildId(guild_data.id);
+
+    TrackInfo::attach(track_handler, sound, user_id).await;
+    attach_panel_events(track_handler, data, guild_id);
+    attach_idle_check(track_handler, data, guild_id);
+    cancel_idle_timer(data, guild_id);
+
+    if !segment.start.is_zero() {
+        let _ = track_handler.seek_time(segment.start);
+    }
+
+    match loop_ {
+        Some(loop_) => {
+            match segment.repeat_period(played_duration) {
+                // songbird only loops whole tracks from the beginning, so part of a sound is
+                // repeated by seeking back each time it finishes
+                Some(period) => {
+                    track_handler
+                        .typemap()
+                        .write()
+                        .await
+                        .insert::<SegmentRepeat>(());
+                    let _ = track_handler.disable_loop();
+                    let _ = track_handler.add_event(
+                        Event::Periodic(period, None),
+                        RepeatSegment {
+                            start: segment.start,
+                            remaining: loop_.count.map(AtomicUsize::new),
+                        },
+                    );
+                }
+
+                None => {
+                    let _ = match loop_.count {
+                        Some(count) => track_handler.loop_for(count),
+
+                        None => track_handler.enable_loop(),
+                    };
+
+                    // without a known length, the best that can be done is to seek back to the
+                    // start as soon as songbird loops
+                    if !segment.start.is_zero() {
+                        let _ = track_handler.add_event(
+                            Event::Track(TrackEvent::Loop),
+                            RepeatSegment {
+                                start: segment.start,
+                                remaining: None,
+                            },
+                        );
+                    }
+                }
+            }
+
+            if let Some(max_duration) = loop_.max_duration {
+                let _ = track_handler.add_event(Event::Delayed(max_duration), StopTrack);
+            }
+
+            remember_loop(track_handler, data, guild_id);
+        }
+
+        None => {
+            let _ = track_handler.disable_loop();
+
+            if let Some(duration) = segment.duration {
+                let _ = track_handler.add_event(Event::Delayed(duration), StopTrack);
+            }
+        }
+    }
+}
+
+fn start_track(
+    track: Track,
+    track_handler: &TrackHandle,
+    call_handler: &mut MutexGuard<'_, Call>,
+    data: &Data,
+    guild_id: GuildId,
+) {
+    call_handler.play(track);
+
+    data.active_tracks
+        .entry(guild_id)
+        .or_default()
+        .push(track_handler.clone());
+}
+
+/// Stop the sounds that have been playing longest, so that `count` more can play without going
+/// over the guild's overlap limit
+async fn make_room(data: &Data, guild_id: GuildId, overlap_limit: u8, count: usize) {
+    let active = live_tracks(&data.active_tracks, guild_id).await;
+    let limit = (overlap_limit as usize).max(1);
+
+    for handle in active
+        .iter()
+        .take((active.len() + count).saturating_sub(limit))
+    {
+        let _ = handle.stop();
+    }
+}
+
+/// Stop the sounds playing outside of the queue. `Call::stop` isn't used, as it also clears
+/// the queue
+fn stop_active_tracks(data: &Data, guild_id: GuildId) {
+    if let Some((_, tracks)) = data.active_tracks.remove(&guild_id) {
+        for handle in tracks {
+            let _ = handle.stop();
+        }
+    }
+}
+
+/// Queue sounds for playback, skipping any that fail to load. Returns the sounds that failed
+pub async fn queue_audio<'a>(
+    sounds: &'a [Sound],
+    user_id: UserId,
+    guild_data: &GuildData,
+    call_handler: &mut MutexGuard<'_, Call>,
+    data: &Data,
+    effects: &Effects,
+) -> Vec<(&'a Sound, Error)> {
+    let mut failed = vec![];
+
+    cancel_idle_timer(data, GuildId(guild_data.id));
+
+    for sound in sounds {
+        let (volume, effects) = playback_volume(sound, guild_data.volume, effects);
+
+        match sound.playable(data, &effects).await {
+            Ok(source) => {
+                let (a, b) = create_player(source);
+
+                TrackInfo::attach(&b, sound, user_id).await;
+                let _ = b.set_volume(volume);
+                attach_panel_events(&b, data, GuildId(guild_data.id));
+                attach_idle_check(&b, data, GuildId(guild_data.id));
+
+                call_handler.enqueue(a);
+            }
+
+            Err(e) => failed.push((sound, e)),
+        }
+    }
+
+    failed
+}
+
 pub async fn join_channel(
     ctx: &poise::serenity_prelude::Context,
+    data: &Data,
     guild: Guild,
     channel_id: ChannelId,
 ) -> (Arc<Mutex<Call>>, JoinResult<()>) {
@@ -96,19 +748,218 @@ pub async fn join_channel(
             .await;
     }
 
+    // if nothing ends up playing, no track ends to start the timer
+    if res.is_ok() {
+        start_idle_timer(data, guild.id).await;
+    }
+
     (call, res)
 }
 
+/// The longest rate limit window a server can set. Plays older than this never count
+const MAX_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Drop a user's plays that have left the window, returning how long until they can play again if
+/// they are at the limit. The user's entry is removed once it has no plays left
+fn prune_plays(
+    data: &Data,
+    key: (GuildId, UserId),
+    limit: usize,
+    window: Duration,
+    now: Instant,
+) -> Option<Duration> {
+    let retry_after = data.recent_plays.get_mut(&key).and_then(|mut plays| {
+        while plays
+            .front()
+            .map_or(false, |played| now.duration_since(*played) >= window)
+        {
+            plays.pop_front();
+        }
+
+        if plays.len() < limit {
+            None
+        } else {
+            Some(window - now.duration_since(plays[0]))
+        }
+    });
+
+    data.recent_plays
+        .remove_if(&key, |_, plays| plays.is_empty());
+
+    retry_after
+}
+
+/// How long until a member can play again, without recording a play. `None` if they are under the
+/// server's limit or hold an exempt role
+pub async fn rate_limit_retry(
+    data: &Data,
+    member: &Member,
+    guild_data: &GuildData,
+) -> Result<Option<Duration>, Error> {
+    let limit = match guild_data.rate_limit_plays {
+        Some(limit) => limit as usize,
+
+        None => return Ok(None),
+    };
+    let window = Duration::from_secs(guild_data.rate_limit_window as u64);
+
+    let retry_after = prune_plays(
+        data,
+        (member.guild_id, member.user.id),
+        limit,
+        window,
+        Instant::now(),
+    );
+
+    match retry_after {
+        // only looked up once a user is over the limit, as most plays never get this far
+        Some(retry_after) => {
+            let exempt_roles = guild_data.exempt_roles(&data.database).await?;
+
+            if member
+                .roles
+                .iter()
+                .any(|role| exempt_roles.contains(&role.0))
+            {
+                Ok(None)
+            } else {
+                Ok(Some(retry_after))
+            }
+        }
+
+        None => Ok(None),
+    }
+}
+
+/// Record a play by a member, failing with `ErrorTypes::RateLimited` if they have already used the
+/// server's allowance for the current window. Members of exempt roles are never limited
+pub async fn check_rate_limit(
+    data: &Data,
+    member: &Member,
+    guild_data: &GuildData,
+) -> Result<(), Error> {
+    if guild_data.rate_limit_plays.is_none() {
+        return Ok(());
+    }
+
+    if let Some(retry_after) = rate_limit_retry(data, member, guild_data).await? {
+        return Err(Box::new(ErrorTypes::RateLimited(retry_after)));
+    }
+
+    data.recent_plays
+        .entry((member.guild_id, member.user.id))
+        .or_default()
+        .push_back(Instant::now());
+
+    Ok(())
+}
+
+/// Forget plays that have left every possible window, so users who stop playing sounds don't keep
+/// an entry forever
+pub async fn sweep_recent_plays(data: Data) {
+    let mut interval = tokio::time::interval(MAX_RATE_LIMIT_WINDOW);
+
+    loop {
+        interval.tick().await;
+
+        let now = Instant::now();
+        data.recent_plays.retain(|_, plays| {
+            plays.back().map_or(false, |played| {
+                now.duration_since(*played) < MAX_RATE_LIMIT_WINDOW
+            })
+        });
+    }
+}
+
+const MAX_QUERY_SOUNDS: usize = 10;
+
+/// The sounds named in a `/play` query. Names joined by `>` play one after another, and names
+/// joined by `+` play at the same time
+#[derive(Debug, PartialEq)]
+pub struct PlayExpression {
+    pub terms: Vec<String>,
+    pub together: bool,
+}
+
+impl FromStr for PlayExpression {
+    type Err = String;
+
+    /// Operators must be separated from names by spaces, so that sounds with `+` or `>` in their
+    /// name can still be played on their own
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut terms = vec![];
+        let mut operator = None;
+        let mut words = vec![];
+
+        for token in s.split_whitespace() {
+            match token {
+                ">" | "+" => {
+                    if words.is_empty() {
+                        return Err(format!("Expected a sound name before `{}`", token));
+                    }
+
+                    match operator {
+                        Some(previous) if previous != token => {
+                            return Err(format!(
+                                "Unexpected `{}` after `{}`. Use `>` to play sounds in order, or `+` to play them together, but not both",
+                                token, previous
+                            ));
+                        }
+
+                        _ => operator = Some(token),
+                    }
+
+                    terms.push(words.join(" "));
+                    words.clear();
+                }
+
+                word => words.push(word),
+            }
+        }
+
+        match operator {
+            None => Ok(PlayExpression {
+                terms: vec![s.trim().to_string()],
+                together: false,
+            }),
+
+            Some(operator) => {
+                if words.is_empty() {
+                    return Err(format!("Expected a sound name after `{}`", operator));
+                }
+
+                terms.push(words.join(" "));
+
+                if terms.len() > MAX_QUERY_SOUNDS {
+                    return Err(format!(
+                        "Too many sounds ({}, max. {})",
+                        terms.len(),
+                        MAX_QUERY_SOUNDS
+                    ));
+                }
+
+                Ok(PlayExpression {
+                    terms,
+                    together: operator == "+",
+                })
+            }
+        }
+    }
+}
+
 pub async fn play_from_query(
     ctx: &poise::serenity_prelude::Context,
     data: &Data,
     guild: Guild,
-    user_id: UserId,
+    member: &Member,
     channel: Option<ChannelId>,
     query: &str,
-    loop_: bool,
-) -> String {
+    loop_: Option<Loop>,
+    segment: Segment,
+    effects: &Effects,
+) -> Result<String, Error> {
     let guild_id = guild.id;
+    let user_id = member.user.id;
 
     let channel_to_join = channel.or_else(|| {
         guild
@@ -117,43 +968,311 @@ pub async fn play_from_query(
             .and_then(|voice_state| voice_state.channel_id)
     });
 
-    match channel_to_join {
-        Some(user_channel) => {
-            let mut sound_vec = data
-                .search_for_sound(query, guild_id, user_id, true)
-                .await
-                .unwrap();
+    let user_channel = match channel_to_join {
+        Some(user_channel) => user_channel,
 
-            let sound_res = sound_vec.first_mut();
+        None => return Ok("You are not in a voice chat!".to_string()),
+    };
 
-            match sound_res {
-                Some(sound) => {
-                    {
-                        let (call_handler, _) =
-                            join_channel(ctx, guild.clone(), user_channel).await;
+    let expression = match query.parse::<PlayExpression>() {
+        Ok(expression) => expression,
 
-                        let guild_data = data.guild_data(guild_id).await.unwrap();
+        Err(reason) => return Ok(reason),
+    };
 
-                        let mut lock = call_handler.lock().await;
+    if loop_.is_some() && expression.terms.len() > 1 {
+        return Ok("Only one sound can be looped at a time".to_string());
+    }
 
-                        play_audio(
-                            sound,
-                            guild_data.read().await.volume,
-                            &mut lock,
-                            &data.database,
-                            loop_,
-                        )
-                        .await
-                        .unwrap();
-                    }
+    if (!segment.start.is_zero() || segment.duration.is_some()) && expression.terms.len() > 1 {
+        return Ok("`start` and `duration` can only be used when playing one sound".to_string());
+    }
 
-                    format!("Playing sound {} with ID {}", sound.name, sound.id)
-                }
+    let guild_data = data.guild_data(guild_id).await?.read().await.clone();
+    let max_duration = guild_data.max_play_duration;
+
+    // queued sounds play one after another, so there is no way to queue a group to play at once
+    if expression.together && guild_data.playback_mode == PlaybackMode::Queue {
+        return Ok(
+            "Sounds can't be played together while this server is in queue mode".to_string(),
+        );
+    }
+
+    if expression.together
+        && guild_data.playback_mode == PlaybackMode::Limited
+        && expression.terms.len() > (guild_data.overlap_limit as usize).max(1)
+    {
+        return Ok(format!(
+            "This server only plays {} sounds at once",
+            guild_data.overlap_limit.max(1)
+        ));
+    }
+
+    // only the part of a sound that will play counts towards the server's limit, for as long as
+    // it takes to play at the chosen speed. The start is checked against the original sound, as
+    // that is what `start` is measured in
+    let speed = effects.speed.unwrap_or(1.0);
+    let too_long = |sound: &Sound| match (segment.length(sound.duration), max_duration) {
+        (Some(length), Some(max_duration)) => length / speed > max_duration as f64,
+
+        _ => false,
+    };
+
+    let mut sounds = vec![];
+    for term in &expression.terms {
+        let sound = data
+            .search_for_sound(term, guild_id, user_id, true)
+            .await?
+            .into_iter()
+            .next();
+
+        match sound {
+            Some(sound)
+                if !segment.start.is_zero()
+                    && sound
+                        .duration
+                        .map_or(false, |duration| segment.start.as_secs_f64() >= duration) =>
+            {
+                return Ok(format!(
+                    "The start time ({}s) is past the end of {} ({:.2}s)",
+                    segment.start.as_secs_f64(),
+                    sound.name,
+                    sound.duration.unwrap_or(0.0)
+                ));
+            }
+
+            Some(sound) if too_long(&sound) => {
+                return Ok(format!(
+                    "Sound {} is too long to play in this server (max. {}s)",
+                    sound.name,
+                    max_duration.unwrap_or(0)
+                ));
+            }
+
+            Some(sound) => sounds.push(sound),
+
+            None if expression.terms.len() == 1 => {
+                return Ok("Couldn't find sound by term provided".to_string());
+            }
+
+            None => return Ok(format!("Couldn't find sound `{}`", term)),
+        }
+    }
+
+    check_rate_limit(data, member, &guild_data).await?;
+
+    let (call_handler, _) = join_channel(ctx, data, guild.clone(), user_channel).await;
+    let mut lock = call_handler.lock().await;
 
-                None => "Couldn't find sound by term provided".to_string(),
+    let names = sounds
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match sounds.as_slice() {
+        [sound] => {
+            if let Err(e) = play_audio(
+                sound,
+                user_id,
+                &guild_data,
+                &mut lock,
+                data,
+                loop_,
+                segment,
+                effects,
+            )
+            .await
+            {
+                warn!(
+                    "Failed to play sound {} in guild {}: {}",
+                    sound.id, guild_id, e
+                );
+
+                return Err(e);
+            }
+
+            Ok(format!("Playing sound {} with ID {}", sound.name, sound.id))
+        }
+
+        sounds if expression.together => {
+            if let Err(e) =
+                play_together(sounds, user_id, &guild_data, &mut lock, data, effects).await
+            {
+                warn!(
+                    "Failed to play sounds {} in guild {}: {}",
+                    names, guild_id, e
+                );
+
+                return Err(e);
             }
+
+            Ok(format!("Playing {} together", names))
         }
 
-        None => "You are not in a voice chat!".to_string(),
+        sounds => {
+            let failed = queue_audio(sounds, user_id, &guild_data, &mut lock, data, effects).await;
+
+            for (sound, e) in &failed {
+                warn!(
+                    "Failed to queue sound {} in guild {}: {}",
+                    sound.id, guild_id, e
+                );
+            }
+
+            let mut content = format!("Playing {} in order.", names);
+            if !failed.is_empty() {
+                content.push_str(&format!(" {} sounds couldn't be loaded.", failed.len()));
+            }
+
+            Ok(content)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boost_rounds_to_steps() {
+        assert_eq!(boost_step(1.05), 1.0);
+        assert_eq!(boost_step(1.2), 1.25);
+        assert_eq!(boost_step(1.8), 2.0);
+        assert_eq!(boost_step(2.6), 3.0);
+        assert_eq!(boost_step(10.2), 4.0);
+    }
+
+    fn segment(start: f64, duration: Option<f64>) -> Segment {
+        Segment {
+            start: Duration::from_secs_f64(start),
+            duration: duration.map(Duration::from_secs_f64),
+        }
+    }
+
+    #[test]
+    fn segment_length() {
+        assert_eq!(Segment::default().length(Some(5.0)), Some(5.0));
+        assert_eq!(segment(2.0, None).length(Some(5.0)), Some(3.0));
+        assert_eq!(segment(2.0, Some(1.0)).length(Some(5.0)), Some(1.0));
+        assert_eq!(segment(2.0, Some(10.0)).length(Some(5.0)), Some(3.0));
+        assert_eq!(segment(6.0, None).length(Some(5.0)), Some(0.0));
+        assert_eq!(segment(2.0, Some(1.0)).length(None), Some(1.0));
+        assert_eq!(segment(2.0, None).length(None), None);
+    }
+
+    #[test]
+    fn segment_at_speed() {
+        let fast = segment(4.0, Some(2.0)).at_speed(2.0);
+        assert_eq!(fast.start, Duration::from_secs(2));
+        assert_eq!(fast.duration, Some(Duration::from_secs(1)));
+
+        let slow = segment(4.0, None).at_speed(0.5);
+        assert_eq!(slow.start, Duration::from_secs(8));
+        assert_eq!(slow.duration, None);
+
+        // a sound played twice as fast lasts half as long, so the segment still runs to its end
+        assert_eq!(
+            segment(4.0, None)
+                .at_speed(2.0)
+                .repeat_period(Some(10.0 / 2.0)),
+            Some(Duration::from_secs_f64(3.0 - FRAME_LENGTH))
+        );
+    }
+
+    #[test]
+    fn segment_repeat_period() {
+        assert_eq!(Segment::default().repeat_period(Some(5.0)), None);
+        assert_eq!(
+            segment(2.0, Some(1.0)).repeat_period(Some(5.0)),
+            Some(Duration::from_secs_f64(1.0))
+        );
+        // runs past the end, so seeks back before the track finishes
+        assert_eq!(
+            segment(2.0, Some(10.0)).repeat_period(Some(5.0)),
+            Some(Duration::from_secs_f64(3.0 - FRAME_LENGTH))
+        );
+        assert_eq!(
+            segment(2.0, None).repeat_period(Some(5.0)),
+            Some(Duration::from_secs_f64(3.0 - FRAME_LENGTH))
+        );
+        assert_eq!(segment(2.0, None).repeat_period(None), None);
+    }
+
+    fn parse(query: &str) -> Result<PlayExpression, String> {
+        query.parse::<PlayExpression>()
+    }
+
+    #[test]
+    fn sequence() {
+        assert_eq!(
+            parse("drum roll > rimshot"),
+            Ok(PlayExpression {
+                terms: vec!["drum roll".to_string(), "rimshot".to_string()],
+                together: false,
+            })
+        );
+    }
+
+    #[test]
+    fn together() {
+        assert_eq!(
+            parse("laugh + applause"),
+            Ok(PlayExpression {
+                terms: vec!["laugh".to_string(), "applause".to_string()],
+                together: true,
+            })
+        );
+    }
+
+    #[test]
+    fn operator_without_spaces_is_part_of_name() {
+        assert_eq!(
+            parse("a>b"),
+            Ok(PlayExpression {
+                terms: vec!["a>b".to_string()],
+                together: false,
+            })
+        );
+    }
+
+    #[test]
+    fn leading_operator() {
+        assert_eq!(
+            parse("> rimshot"),
+            Err("Expected a sound name before `>`".to_string())
+        );
+    }
+
+    #[test]
+    fn trailing_operator() {
+        assert_eq!(
+            parse("laugh +"),
+            Err("Expected a sound name after `+`".to_string())
+        );
+    }
+
+    #[test]
+    fn mixed_operators() {
+        assert!(parse("a > b + c")
+            .unwrap_err()
+            .starts_with("Unexpected `+` after `>`"));
+    }
+
+    #[test]
+    fn too_many_terms() {
+        let query = (0..=MAX_QUERY_SOUNDS)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(" > ");
+
+        assert_eq!(
+            parse(&query),
+            Err(format!(
+                "Too many sounds ({}, max. {})",
+                MAX_QUERY_SOUNDS + 1,
+                MAX_QUERY_SOUNDS
+            ))
+        );
     }
 }